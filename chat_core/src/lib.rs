@@ -1,7 +1,11 @@
 mod utils;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use sqlx::{
+    error::BoxDynError,
+    postgres::{PgHasArrayType, PgTypeInfo, PgValueRef},
+    Decode, FromRow, PgPool, Postgres,
+};
 pub use utils::*;
 use utoipa::ToSchema;
 pub mod middlewares;
@@ -15,14 +19,70 @@ pub struct User {
     #[sqlx(default)]
     #[serde(skip)]
     pub password_hash: Option<String>,
+    // part of the token claims, tokens issued before roles existed are of members
+    #[sqlx(default)]
+    #[serde(default)]
+    pub role: Role,
     pub created_at: DateTime<Utc>,
 }
 
+/// what a user may do in their workspace, see `Role::can`
+#[derive(Debug, Clone, Copy, Default, ToSchema, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "workspace_role", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Owner,
+    Admin,
+    #[default]
+    Member,
+    Guest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    CreatePublicChannel,
+    /// remove others from a chat, anyone may leave
+    RemoveChatMembers,
+    /// delete a group or a channel with its messages, a single chat may be deleted by either side
+    DeleteChat,
+    /// edit or delete messages of others
+    ModerateMessages,
+    ManageInvites,
+    /// collect unused uploads
+    ManageFiles,
+    /// rename the workspace and change its settings
+    ManageWorkspace,
+    /// deactivate and reactivate members
+    ManageMembers,
+    TransferOwnership,
+    DeleteWorkspace,
+}
+
+impl Role {
+    pub fn can(self, permission: Permission) -> bool {
+        match permission {
+            Permission::CreatePublicChannel => self != Role::Guest,
+            Permission::RemoveChatMembers
+            | Permission::DeleteChat
+            | Permission::ModerateMessages
+            | Permission::ManageInvites
+            | Permission::ManageFiles
+            | Permission::ManageWorkspace
+            | Permission::ManageMembers => matches!(self, Role::Owner | Role::Admin),
+            Permission::TransferOwnership | Permission::DeleteWorkspace => self == Role::Owner,
+        }
+    }
+}
+
 #[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
 pub struct Workspace {
     pub id: i64,
     pub name: String,
     pub owner_id: i64,
+    /// chats new members are added to
+    pub default_channels: Vec<i64>,
+    /// days messages are kept, 0 keeps them forever
+    pub retention_days: i32,
     pub created_at: DateTime<Utc>,
 }
 
@@ -48,9 +108,27 @@ pub struct Chat {
     pub id: i64,
     pub ws_id: i64,
     pub name: Option<String>,
+    /// what the chat is about
+    pub topic: Option<String>,
     pub r#type: ChatType,
     pub members: Vec<i64>,
     pub created_at: DateTime<Utc>,
+    // unread messages of the caller, filled in when listing chats
+    #[sqlx(default)]
+    #[serde(default)]
+    pub unread: i64,
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub last_message: Option<Message>,
+}
+
+/// how far a user has read a chat
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct ChatRead {
+    pub chat_id: i64,
+    pub user_id: i64,
+    pub last_read_message_id: i64,
+    pub updated_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
@@ -59,8 +137,185 @@ pub struct Message {
     pub chat_id: i64,
     pub sender_id: i64,
     pub content: String,
-    pub files: Vec<String>,
+    // stored as urls, name/size/mime are filled in from the files table when available
+    pub files: Vec<MessageFile>,
     pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub edited_at: Option<DateTime<Utc>>,
+    // deleted messages are kept as tombstones with content and files cleared
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
+    // set on replies, threads are one level deep
+    #[serde(default)]
+    pub parent_id: Option<i64>,
+    #[serde(default)]
+    pub reply_count: i32,
+    #[serde(default)]
+    pub last_reply_at: Option<DateTime<Utc>>,
+    // filled in for the caller when listing messages
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+}
+
+/// a file attached to a message
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(from = "MessageFileRepr")]
+pub struct MessageFile {
+    pub url: String,
+    pub name: String,
+    // unknown for files without a record, and in events, which only carry the url
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime: Option<String>,
+    // pixels, for images
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<i32>,
+}
+
+// messages in pg notifications still carry the bare urls
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MessageFileRepr {
+    Url(String),
+    File {
+        url: String,
+        name: String,
+        #[serde(default)]
+        size: Option<i64>,
+        #[serde(default)]
+        mime: Option<String>,
+        #[serde(default)]
+        width: Option<i32>,
+        #[serde(default)]
+        height: Option<i32>,
+    },
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct Reaction {
+    pub emoji: String,
+    pub count: i64,
+    // whether the caller is one of them
+    pub me: bool,
+}
+
+impl ChatRead {
+    /// advance the user's read position in the chat, it never moves backwards. `None` if the
+    /// message isn't in a chat of the workspace the user is a member of. shared by chat_server's
+    /// `POST /api/chats/:id/read` and notify_server's `MarkRead` frame.
+    pub async fn mark(
+        pool: &PgPool,
+        chat_id: i64,
+        user_id: i64,
+        ws_id: i64,
+        message_id: i64,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let id: Option<i64> = sqlx::query_scalar(
+            r#"
+            SELECT m.id FROM messages m JOIN chats c ON c.id = m.chat_id
+            WHERE m.id = $1 AND m.chat_id = $2 AND c.ws_id = $3 AND $4 = ANY(c.members)
+            "#,
+        )
+        .bind(message_id)
+        .bind(chat_id)
+        .bind(ws_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+        if id.is_none() {
+            return Ok(None);
+        }
+
+        // the row is only touched when it advances, so no event is sent otherwise
+        sqlx::query(
+            r#"
+            INSERT INTO chat_reads (chat_id, user_id, last_read_message_id)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (chat_id, user_id) DO UPDATE
+            SET last_read_message_id = EXCLUDED.last_read_message_id, updated_at = now()
+            WHERE chat_reads.last_read_message_id < EXCLUDED.last_read_message_id
+            "#,
+        )
+        .bind(chat_id)
+        .bind(user_id)
+        .bind(message_id)
+        .execute(pool)
+        .await?;
+
+        let read = sqlx::query_as("SELECT * FROM chat_reads WHERE chat_id = $1 AND user_id = $2")
+            .bind(chat_id)
+            .bind(user_id)
+            .fetch_one(pool)
+            .await?;
+        Ok(Some(read))
+    }
+}
+
+impl MessageFile {
+    /// what's known from the url alone, the name is the last path segment
+    pub fn from_url(url: impl Into<String>) -> Self {
+        let url = url.into();
+        let name = url.rsplit('/').next().unwrap_or_default().to_string();
+        Self {
+            url,
+            name,
+            size: None,
+            mime: None,
+            width: None,
+            height: None,
+        }
+    }
+}
+
+impl From<MessageFileRepr> for MessageFile {
+    fn from(repr: MessageFileRepr) -> Self {
+        match repr {
+            MessageFileRepr::Url(url) => Self::from_url(url),
+            MessageFileRepr::File {
+                url,
+                name,
+                size,
+                mime,
+                width,
+                height,
+            } => Self {
+                url,
+                name,
+                size,
+                mime,
+                width,
+                height,
+            },
+        }
+    }
+}
+
+// `messages.files` is a text[] of urls
+impl sqlx::Type<Postgres> for MessageFile {
+    fn type_info() -> PgTypeInfo {
+        <String as sqlx::Type<Postgres>>::type_info()
+    }
+
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        <String as sqlx::Type<Postgres>>::compatible(ty)
+    }
+}
+
+impl PgHasArrayType for MessageFile {
+    fn array_type_info() -> PgTypeInfo {
+        <String as PgHasArrayType>::array_type_info()
+    }
+}
+
+impl<'r> Decode<'r, Postgres> for MessageFile {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        let url = <String as Decode<Postgres>>::decode(value)?;
+        Ok(Self::from_url(url))
+    }
 }
 
 impl User {
@@ -71,7 +326,30 @@ impl User {
             fullname: fullname.to_string(),
             email: email.to_string(),
             password_hash: None,
+            role: Role::Member,
             created_at: chrono::Utc::now(),
         }
     }
+
+    /// whether the user's access token with id `jti` can't be used anymore: it's on the deny
+    /// list, they are no longer an active member of the workspace it was issued for, or their
+    /// role there changed since, e.g. by an ownership transfer. shared by both servers' token
+    /// verification.
+    pub async fn is_token_revoked(&self, pool: &PgPool, jti: Option<&str>) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar(
+            r#"
+            SELECT EXISTS(SELECT 1 FROM revoked_tokens WHERE jti = $1)
+              OR NOT EXISTS(
+                SELECT 1 FROM workspace_members
+                WHERE user_id = $2 AND ws_id = $3 AND deactivated_at IS NULL AND role = $4
+              )
+            "#,
+        )
+        .bind(jti)
+        .bind(self.id)
+        .bind(self.ws_id)
+        .bind(self.role)
+        .fetch_one(pool)
+        .await
+    }
 }
@@ -1,7 +1,7 @@
 mod auth;
 mod request_id;
 mod server_time;
-use std::fmt;
+use std::{fmt, future::Future};
 
 pub use auth::verify_token;
 use axum::{middleware::from_fn, Router};
@@ -19,7 +19,7 @@ use crate::User;
 
 pub trait TokenVerify {
     type Error: fmt::Debug;
-    fn verify(&self, token: &str) -> Result<User, Self::Error>;
+    fn verify(&self, token: &str) -> impl Future<Output = Result<User, Self::Error>> + Send;
 }
 
 const REQUEST_ID_HEADER: &str = "x-request-id";
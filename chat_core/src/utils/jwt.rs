@@ -1,44 +1,156 @@
-use jwt_simple::prelude::*;
+use std::collections::HashMap;
+
+use jwt_simple::{prelude::*, JWTError};
+use utoipa::ToSchema;
 
 use crate::User;
 
-const JWT_DURATION: u64 = 60 * 60 * 24;
+// access tokens are short-lived, clients renew them with a refresh token
+const JWT_DURATION: u64 = 60 * 15;
 const JWT_ISS: &str = "chat_server";
 const JWT_AUD: &str = "chat_web";
 
+/// the key new tokens are signed with, its `kid` (if any) goes into the token header
 #[allow(unused)]
 pub struct EncodingKey(Ed25519KeyPair);
 
-#[allow(unused)]
-pub struct DecodingKey(Ed25519PublicKey);
+/// keyring of public keys indexed by `kid`, a token is accepted if any of them signed it
+#[derive(Default)]
+pub struct DecodingKey(HashMap<String, Ed25519PublicKey>);
+
+/// JSON Web Key Set as served on `/.well-known/jwks.json`
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+/// an Ed25519 public key in JWK form (RFC 8037)
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct Jwk {
+    pub kty: String,
+    pub crv: String,
+    pub kid: String,
+    pub alg: String,
+    #[serde(rename = "use")]
+    pub usage: String,
+    pub x: String,
+}
 
 impl EncodingKey {
     pub fn load(pem: &str) -> Result<Self, jwt_simple::Error> {
         Ok(Self(Ed25519KeyPair::from_pem(pem)?))
     }
 
+    /// load a signing key, tokens signed with it carry `kid` in their header
+    pub fn load_with_kid(kid: &str, pem: &str) -> Result<Self, jwt_simple::Error> {
+        Ok(Self(Ed25519KeyPair::from_pem(pem)?.with_key_id(kid)))
+    }
+
     #[allow(unused)]
     pub fn sign(&self, user: impl Into<User>) -> Result<String, jwt_simple::Error> {
         let claims = Claims::with_custom_claims(user.into(), Duration::from_secs(JWT_DURATION));
-        let claims = claims.with_issuer(JWT_ISS).with_audience(JWT_AUD);
+        let claims = claims
+            .with_issuer(JWT_ISS)
+            .with_audience(JWT_AUD)
+            .with_jwt_id(uuid::Uuid::now_v7().to_string());
         self.0.sign(claims)
     }
 }
 
 impl DecodingKey {
+    /// a keyring with a single key without `kid`
     pub fn load(pem: &str) -> Result<Self, jwt_simple::Error> {
-        Ok(Self(Ed25519PublicKey::from_pem(pem)?))
+        let mut dk = Self::default();
+        dk.add("", pem)?;
+        Ok(dk)
+    }
+
+    /// add (or replace) the public key for `kid`
+    pub fn add(&mut self, kid: &str, pem: &str) -> Result<(), jwt_simple::Error> {
+        let key = Ed25519PublicKey::from_pem(pem)?;
+        self.0.insert(kid.to_string(), key);
+        Ok(())
+    }
+
+    /// merge the keys of `other` into this keyring
+    pub fn extend(&mut self, other: DecodingKey) {
+        self.0.extend(other.0);
+    }
+
+    pub fn contains(&self, kid: &str) -> bool {
+        self.0.contains_key(kid)
+    }
+
+    /// the `kid` of a token, read from its header without verifying it
+    pub fn token_kid(token: &str) -> Option<String> {
+        Token::decode_metadata(token)
+            .ok()?
+            .key_id()
+            .map(|kid| kid.to_string())
     }
 
     #[allow(unused)]
     pub fn verify(&self, token: &str) -> Result<User, jwt_simple::Error> {
+        Ok(self.decode(token)?.custom)
+    }
+
+    /// verify the token and return all of its claims, e.g. `jwt_id` for revocation checks
+    pub fn decode(&self, token: &str) -> Result<JWTClaims<User>, jwt_simple::Error> {
         let ops = VerificationOptions {
             allowed_issuers: Some(HashSet::from_strings(&[JWT_ISS])),
             allowed_audiences: Some(HashSet::from_strings(&[JWT_AUD])),
             ..Default::default()
         };
-        let claims = self.0.verify_token::<User>(token, Some(ops))?;
-        Ok(claims.custom)
+        match Self::token_kid(token) {
+            Some(kid) => match self.0.get(&kid) {
+                Some(key) => key.verify_token::<User>(token, Some(ops)),
+                None => Err(JWTError::KeyIdentifierMismatch.into()),
+            },
+            // tokens issued before key rotation carry no kid, try every key
+            None => {
+                let mut ret = Err(JWTError::InvalidSignature.into());
+                for key in self.0.values() {
+                    ret = key.verify_token::<User>(token, Some(ops.clone()));
+                    if ret.is_ok() {
+                        break;
+                    }
+                }
+                ret
+            }
+        }
+    }
+
+    /// public keys with a `kid` as a JWKS document
+    pub fn jwks(&self) -> Jwks {
+        let mut keys: Vec<_> = self
+            .0
+            .iter()
+            .filter(|(kid, _)| !kid.is_empty())
+            .map(|(kid, key)| Jwk {
+                kty: "OKP".to_string(),
+                crv: "Ed25519".to_string(),
+                kid: kid.clone(),
+                alg: "EdDSA".to_string(),
+                usage: "sig".to_string(),
+                x: Base64UrlSafeNoPadding::encode_to_string(key.to_bytes()).unwrap_or_default(),
+            })
+            .collect();
+        keys.sort_by(|a, b| a.kid.cmp(&b.kid));
+        Jwks { keys }
+    }
+
+    /// build a keyring from a JWKS document, keys which aren't Ed25519 are skipped
+    pub fn from_jwks(jwks: &Jwks) -> Result<Self, jwt_simple::Error> {
+        let mut dk = Self::default();
+        for jwk in &jwks.keys {
+            if jwk.kty != "OKP" || jwk.crv != "Ed25519" {
+                continue;
+            }
+            let raw = Base64UrlSafeNoPadding::decode_to_vec(&jwk.x, None)?;
+            let key = Ed25519PublicKey::from_bytes(&raw)?;
+            dk.0.insert(jwk.kid.clone(), key);
+        }
+        Ok(dk)
     }
 }
 
@@ -68,6 +180,58 @@ mod tests {
         let token = ek.sign(user.clone())?;
         let user2 = dk.verify(&token)?;
         assert_eq!(user, user2);
+
+        let claims = dk.decode(&token)?;
+        assert!(claims.jwt_id.is_some());
+        assert!(claims.expires_at.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn jwt_key_rotation_should_work() -> Result<()> {
+        let old = Ed25519KeyPair::generate();
+        let new = Ed25519KeyPair::generate();
+        let old_ek = EncodingKey::load_with_kid("old", &old.to_pem())?;
+        let new_ek = EncodingKey::load_with_kid("new", &new.to_pem())?;
+        let legacy_ek = EncodingKey::load(&old.to_pem())?;
+        let user = User::new(1, "ssk", "123@wxample.com");
+
+        let mut dk = DecodingKey::default();
+        dk.add("old", &old.public_key().to_pem())?;
+        dk.add("new", &new.public_key().to_pem())?;
+
+        let token = new_ek.sign(user.clone())?;
+        assert_eq!(DecodingKey::token_kid(&token).as_deref(), Some("new"));
+        assert_eq!(dk.verify(&token)?, user);
+        assert_eq!(dk.verify(&old_ek.sign(user.clone())?)?, user);
+        assert_eq!(dk.verify(&legacy_ek.sign(user.clone())?)?, user);
+
+        // a retired key is no longer accepted
+        let mut dk = DecodingKey::default();
+        dk.add("new", &new.public_key().to_pem())?;
+        assert!(dk.verify(&old_ek.sign(user.clone())?).is_err());
+        assert!(dk.verify(&legacy_ek.sign(user.clone())?).is_err());
+
+        // a kid can't be used to smuggle in another key's signature
+        let forged = EncodingKey::load_with_kid("new", &old.to_pem())?;
+        assert!(dk.verify(&forged.sign(user)?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn jwks_round_trip_should_work() -> Result<()> {
+        let key = Ed25519KeyPair::generate();
+        let mut dk = DecodingKey::default();
+        dk.add("k1", &key.public_key().to_pem())?;
+        dk.add("", &key.public_key().to_pem())?;
+        let jwks = dk.jwks();
+        assert_eq!(jwks.keys.len(), 1);
+        assert_eq!(jwks.keys[0].kid, "k1");
+
+        let dk2 = DecodingKey::from_jwks(&jwks)?;
+        let ek = EncodingKey::load_with_kid("k1", &key.to_pem())?;
+        let user = User::new(1, "ssk", "123@wxample.com");
+        assert_eq!(dk2.verify(&ek.sign(user.clone())?)?, user);
         Ok(())
     }
 }
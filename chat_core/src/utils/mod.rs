@@ -1,2 +1,2 @@
 mod jwt;
-pub use jwt::{DecodingKey, EncodingKey};
+pub use jwt::{DecodingKey, EncodingKey, Jwk, Jwks};
@@ -12,6 +12,28 @@ INSERT INTO users(ws_id, email, fullname, password_hash)
 (1, 'charlie@acme.org', 'Charlie Chen', '$argon2id$v=19$m=19456,t=2,p=1$MxGhY+ib/kplwBPLa7u2ug$c5h9u7Sc8Px8J5+qgNdOjSY7ZJO2QN4rugKpapGW4XU'),
 (1, 'daisy@acme.org', 'Daisy Chen', '$argon2id$v=19$m=19456,t=2,p=1$MxGhY+ib/kplwBPLa7u2ug$c5h9u7Sc8Px8J5+qgNdOjSY7ZJO2QN4rugKpapGW4XU');
 
+-- insert 2 users in another workspace
+INSERT INTO users(ws_id, email, fullname, password_hash)
+  VALUES (2, 'frank@foo.org', 'Frank Foo', '$argon2id$v=19$m=19456,t=2,p=1$MxGhY+ib/kplwBPLa7u2ug$c5h9u7Sc8Px8J5+qgNdOjSY7ZJO2QN4rugKpapGW4XU'),
+(2, 'grace@foo.org', 'Grace Foo', '$argon2id$v=19$m=19456,t=2,p=1$MxGhY+ib/kplwBPLa7u2ug$c5h9u7Sc8Px8J5+qgNdOjSY7ZJO2QN4rugKpapGW4XU');
+
+-- the first user of a workspace owns it, as on signup
+UPDATE workspaces SET owner_id = 1 WHERE id = 1;
+UPDATE workspaces SET owner_id = 6 WHERE id = 2;
+INSERT INTO workspace_members(ws_id, user_id, role)
+SELECT
+  ws_id,
+  id,
+  CASE WHEN id IN (1, 6) THEN
+    'owner'
+  ELSE
+    'member'
+  END::workspace_role
+FROM
+  users
+WHERE
+  id > 0;
+
 -- insert 4 chats
 -- insert public/private channel
 INSERT INTO chats(ws_id, name, type, members)
@@ -23,6 +45,10 @@ INSERT INTO chats(ws_id, type, members)
   VALUES (1, 'single', '{1,2}'),
 (1, 'group', '{1,3,4}');
 
+-- insert chat in another workspace
+INSERT INTO chats(ws_id, name, type, members)
+  VALUES (2, 'foo', 'private_channel', '{6,7}');
+
 INSERT INTO messages(chat_id, sender_id, content)
   VALUES (1, 1, 'Hello, world!'),
 (1, 2, 'Hi, there!'),
@@ -34,3 +60,6 @@ INSERT INTO messages(chat_id, sender_id, content)
 (1, 3, 'How are you?'),
 (1, 1, 'Hello, world!'),
 (1, 1, 'Hello, world!');
+
+INSERT INTO messages(chat_id, sender_id, content)
+  VALUES (5, 6, 'Hello, foo!');
@@ -1,6 +1,7 @@
-use std::{env, fs::File, path::PathBuf};
+use std::{env, fmt, fs::File, path::PathBuf};
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
+use chat_core::{DecodingKey, EncodingKey};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -10,7 +11,17 @@ pub struct AppConfig {
 }
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthConfig {
-    pub sk: String,
+    /// kid of the key new tokens are signed with
+    pub current: String,
+    /// every key in here is accepted for verification, retired keys only need `pk`
+    pub keys: Vec<AuthKey>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthKey {
+    pub kid: String,
+    #[serde(default)]
+    pub sk: Option<String>,
     pub pk: String,
 }
 
@@ -19,7 +30,151 @@ pub struct ServerConfig {
     pub port: u16,
     pub db_url: String,
     pub base_dir: PathBuf,
+    /// where uploaded files are kept, `base_dir` unless configured otherwise
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub upload: UploadConfig,
+    #[serde(default)]
+    pub file_gc: FileGcConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+}
+
+/// removal of uploaded files no message refers to
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FileGcConfig {
+    /// seconds between runs, 0 turns the periodic run off
+    pub interval: u64,
+    /// seconds a file is kept after its last upload, it may be about to be sent
+    pub grace_period: u64,
+}
+
+/// removal of messages past the retention period of their workspace
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// seconds between runs, 0 turns it off
+    pub interval: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UploadConfig {
+    /// bytes per file
+    pub max_file_size: u64,
+    /// bytes per upload request, all files together
+    pub max_request_size: u64,
+    /// bytes a workspace may store unless it has a quota of its own
+    pub workspace_quota: u64,
+    /// mime types (`image/png`, `image/*`) or extensions (`.pdf`) which may be uploaded, empty allows all
+    pub allow: Vec<String>,
+    /// same as `allow` but refused, checked first
+    pub deny: Vec<String>,
+    /// longest side in pixels of the webp thumbnails made of uploaded images
+    pub thumbnail_sizes: Vec<u32>,
 }
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StorageConfig {
+    #[default]
+    Local,
+    S3(S3Config),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct S3Config {
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// `endpoint/bucket/key` urls, which MinIO and most self-hosted stores expect
+    #[serde(default = "default_path_style")]
+    pub path_style: bool,
+}
+
+// the config ends up in logs through AppState, keep the secret out of them
+impl fmt::Debug for S3Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("S3Config")
+            .field("endpoint", &self.endpoint)
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .field("access_key", &self.access_key)
+            .field("secret_key", &"***")
+            .field("path_style", &self.path_style)
+            .finish()
+    }
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            max_file_size: 20 * 1024 * 1024,
+            max_request_size: 100 * 1024 * 1024,
+            workspace_quota: 1024 * 1024 * 1024,
+            allow: vec![],
+            deny: vec![],
+            thumbnail_sizes: vec![256, 1024],
+        }
+    }
+}
+
+impl Default for FileGcConfig {
+    fn default() -> Self {
+        Self {
+            interval: 3600,
+            grace_period: 24 * 3600,
+        }
+    }
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self { interval: 3600 }
+    }
+}
+
+impl UploadConfig {
+    /// whether a file with this name and mime type may be uploaded
+    pub fn accepts(&self, filename: &str, mime: &str) -> bool {
+        let matches = |pattern: &String| match pattern.strip_prefix('.') {
+            Some(ext) => filename
+                .rsplit_once('.')
+                .is_some_and(|(_, v)| v.eq_ignore_ascii_case(ext)),
+            None => match pattern.strip_suffix("/*") {
+                Some(kind) => mime.split('/').next() == Some(kind),
+                None => pattern == mime,
+            },
+        };
+        !self.deny.iter().any(matches) && (self.allow.is_empty() || self.allow.iter().any(matches))
+    }
+}
+
+fn default_path_style() -> bool {
+    true
+}
+impl AuthConfig {
+    pub fn load_keys(&self) -> Result<(EncodingKey, DecodingKey)> {
+        let mut dk = DecodingKey::default();
+        for key in &self.keys {
+            dk.add(&key.kid, &key.pk)
+                .with_context(|| format!("load pk {} failed", key.kid))?;
+        }
+        let sk = self
+            .keys
+            .iter()
+            .find(|key| key.kid == self.current)
+            .and_then(|key| key.sk.as_deref())
+            .with_context(|| format!("sk of current key {} not found", self.current))?;
+        let ek = EncodingKey::load_with_kid(&self.current, sk).context("load sk failed")?;
+        Ok((ek, dk))
+    }
+}
+
 impl AppConfig {
     pub fn load() -> Result<Self> {
         // read from ./app.yml ,or /etc/config/app.yml ,or from env CHAT_CONFIG
@@ -36,3 +191,38 @@ impl AppConfig {
         Ok(ret?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upload_types_should_be_checked() {
+        let config = UploadConfig {
+            allow: vec!["image/*".to_string(), ".pdf".to_string(), "text/plain".to_string()],
+            deny: vec!["image/svg+xml".to_string()],
+            ..Default::default()
+        };
+        assert!(config.accepts("a.png", "image/png"));
+        assert!(config.accepts("a.PDF", "application/octet-stream"));
+        assert!(config.accepts("notes", "text/plain"));
+        assert!(!config.accepts("a.svg", "image/svg+xml"));
+        assert!(!config.accepts("a.exe", "application/x-msdownload"));
+        assert!(UploadConfig::default().accepts("a.exe", "application/x-msdownload"));
+    }
+
+    #[test]
+    fn s3_secret_should_not_be_printed() {
+        let config = S3Config {
+            endpoint: "http://localhost:9000".to_string(),
+            bucket: "chat".to_string(),
+            region: "us-east-1".to_string(),
+            access_key: "minio".to_string(),
+            secret_key: "very-secret".to_string(),
+            path_style: true,
+        };
+        let printed = format!("{:?}", config);
+        assert!(printed.contains("minio"));
+        assert!(!printed.contains("very-secret"));
+    }
+}
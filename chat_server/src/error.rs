@@ -17,18 +17,60 @@ pub enum AppError {
     #[error("email already exists: {0}")]
     EmailAlreadyExists(String),
 
+    #[error("workspace already exists: {0}")]
+    WorkspaceAlreadyExists(String),
+
+    #[error("signup error: {0}")]
+    SignupError(String),
+
+    #[error("create invite error: {0}")]
+    CreateInviteError(String),
+
+    #[error("update workspace error: {0}")]
+    UpdateWorkspaceError(String),
+
     #[error("create chat error: {0}")]
     CreateChatError(String),
 
+    #[error("update chat error: {0}")]
+    UpdateChatError(String),
+
     #[error("create message error: {0}")]
     CreateMessageError(String),
 
+    #[error("update message error: {0}")]
+    UpdateMessageError(String),
+
+    #[error("reaction error: {0}")]
+    ReactionError(String),
+
+    #[error("search error: {0}")]
+    SearchError(String),
+
     #[error("{0}")]
     CreateChatFileError(String),
 
+    #[error("invalid upload: {0}")]
+    InvalidUpload(String),
+
+    #[error("payload too large: {0}")]
+    PayloadTooLarge(String),
+
+    #[error("unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+
     #[error("Not found: {0}")]
     NotFound(String),
 
+    #[error("storage error: {0}")]
+    StorageError(String),
+
     #[error("io error: {0}")]
     IoError(#[from] std::io::Error),
 
@@ -57,11 +99,25 @@ impl IntoResponse for AppError {
             Self::JwtError(_) => StatusCode::FORBIDDEN,
             Self::HttpHeaderError(_) => StatusCode::UNPROCESSABLE_ENTITY,
             Self::EmailAlreadyExists(_) => StatusCode::CONFLICT,
+            Self::WorkspaceAlreadyExists(_) => StatusCode::CONFLICT,
+            Self::SignupError(_) => StatusCode::BAD_REQUEST,
+            Self::CreateInviteError(_) => StatusCode::BAD_REQUEST,
+            Self::UpdateWorkspaceError(_) => StatusCode::BAD_REQUEST,
             Self::CreateMessageError(_) => StatusCode::BAD_REQUEST,
+            Self::UpdateMessageError(_) => StatusCode::BAD_REQUEST,
+            Self::ReactionError(_) => StatusCode::BAD_REQUEST,
+            Self::SearchError(_) => StatusCode::BAD_REQUEST,
             Self::CreateChatError(_) => StatusCode::BAD_REQUEST,
+            Self::UpdateChatError(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::PermissionDenied(_) => StatusCode::FORBIDDEN,
             Self::NotFound(_) => StatusCode::NOT_FOUND,
             Self::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::StorageError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::CreateChatFileError(_) => StatusCode::BAD_REQUEST,
+            Self::InvalidUpload(_) => StatusCode::BAD_REQUEST,
+            Self::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
         };
         (status, Json(ErrorOutput::new(self.to_string()))).into_response()
     }
@@ -1,16 +1,34 @@
 use axum::http::StatusCode;
 use axum::Json;
-use axum::{extract::State, response::IntoResponse};
+use axum::{extract::State, response::IntoResponse, Extension};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use chat_core::User;
+use chrono::DateTime;
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
 use crate::error::{AppError, ErrorOutput};
-use crate::models::{CreateUser, SigninUser};
+use crate::models::{CreateUser, RefreshInput, SigninUser};
 use crate::AppState;
 
 #[derive(Debug, ToSchema, Serialize, Deserialize)]
 pub struct AuthOutput {
     token: String,
+    refresh_token: String,
+}
+
+impl AppState {
+    /// tokens for the user in the workspace of `user.ws_id`
+    pub(super) async fn auth_output(&self, user: User) -> Result<AuthOutput, AppError> {
+        let refresh_token = self
+            .create_refresh_token(user.id as _, user.ws_id as _)
+            .await?;
+        let token = self.ek.sign(user)?;
+        Ok(AuthOutput { token, refresh_token })
+    }
 }
 
 #[utoipa::path(
@@ -23,8 +41,7 @@ pub(crate) async fn signup_handler(
     Json(input): Json<CreateUser>,
 ) -> Result<impl IntoResponse, AppError> {
     let user = state.create_user(&input).await?;
-    let token = state.ek.sign(user)?;
-    let body = Json(AuthOutput { token });
+    let body = Json(state.auth_output(user).await?);
     Ok((StatusCode::CREATED, body))
 }
 
@@ -42,8 +59,8 @@ pub(crate) async fn signin_handler(
     let user = state.verify_user(&input).await?;
     match user {
         Some(user) => {
-            let token = state.ek.sign(user)?;
-            Ok((StatusCode::OK, Json(AuthOutput { token })).into_response())
+            let body = Json(state.auth_output(user).await?);
+            Ok((StatusCode::OK, body).into_response())
         }
         None => {
             let body = Json(ErrorOutput::new("Invalid email or password"));
@@ -52,9 +69,69 @@ pub(crate) async fn signin_handler(
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/refresh",
+    request_body = RefreshInput,
+    responses(
+        (status = 200, description = "Tokens rotated", body = AuthOutput),
+        (status = 401, description = "Invalid refresh token", body = ErrorOutput),
+    )
+)]
+pub(crate) async fn refresh_handler(
+    State(state): State<AppState>,
+    Json(input): Json<RefreshInput>,
+) -> Result<impl IntoResponse, AppError> {
+    let (user, refresh_token) = state.rotate_refresh_token(&input.refresh_token).await?;
+    let token = state.ek.sign(user)?;
+    Ok(Json(AuthOutput { token, refresh_token }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/signout",
+    request_body = Option<RefreshInput>,
+    responses(
+        (status = 204, description = "Tokens revoked"),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn signout_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    input: Option<Json<RefreshInput>>,
+) -> Result<impl IntoResponse, AppError> {
+    let claims = state.dk.decode(bearer.token())?;
+    if let (Some(jti), Some(expires_at)) = (claims.jwt_id, claims.expires_at) {
+        let expires_at = DateTime::from_timestamp(expires_at.as_secs() as _, 0).unwrap_or_default();
+        state.revoke_access_token(&jti, expires_at).await?;
+    }
+    if let Some(Json(input)) = input {
+        state
+            .revoke_refresh_token(&input.refresh_token, user.id as _)
+            .await?;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/.well-known/jwks.json",
+    responses(
+        (status = 200, description = "Public keys tokens may be signed with", body = Jwks),
+    )
+)]
+pub(crate) async fn jwks_handler(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.dk.jwks())
+}
+
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
+    use chat_core::{middlewares::TokenVerify, DecodingKey, Jwks};
     use http_body_util::BodyExt;
 
     use super::*;
@@ -62,7 +139,7 @@ mod tests {
     #[tokio::test]
     async fn singup_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
-        let input = CreateUser::new("none", "ssk", "123456@qq.com", "pass123444");
+        let input = CreateUser::new("ssk", "ssk", "123456@qq.com", "pass123444");
         let ret = signup_handler(State(state), Json(input))
             .await?
             .into_response();
@@ -75,7 +152,7 @@ mod tests {
     #[tokio::test]
     async fn signup_duplicate_user_should_409() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
-        let input = CreateUser::new("none", "ssk1", "ssk@acme.org", "pass123444");
+        let input = CreateUser::new("ssk1", "ssk1", "ssk@acme.org", "pass123444");
         signup_handler(State(state.clone()), Json(input.clone())).await?;
         let ret = signup_handler(State(state), Json(input.clone()))
             .await
@@ -103,10 +180,86 @@ mod tests {
         let body = ret.into_body().collect().await?.to_bytes();
         let ret = serde_json::from_slice::<AuthOutput>(&body)?;
         assert_ne!(ret.token, "");
+        assert_ne!(ret.refresh_token, "");
 
         Ok(())
     }
 
+    #[tokio::test]
+    async fn refresh_should_rotate_tokens() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let output = state.auth_output(user).await?;
+
+        let input = RefreshInput {
+            refresh_token: output.refresh_token.clone(),
+        };
+        let ret = refresh_handler(State(state.clone()), Json(input.clone()))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let ret = serde_json::from_slice::<AuthOutput>(&body)?;
+        assert_ne!(ret.refresh_token, output.refresh_token);
+
+        // the old refresh token can't be used again
+        let ret = refresh_handler(State(state), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn signout_should_revoke_tokens() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let output = state.auth_output(user.clone()).await?;
+        let bearer = Authorization::bearer(&output.token)?;
+        let input = RefreshInput {
+            refresh_token: output.refresh_token.clone(),
+        };
+
+        // someone else's refresh token is left alone
+        let other = state.find_user_by_id(2).await?.expect("user should exist");
+        let other = state.auth_output(other).await?;
+        let other = RefreshInput {
+            refresh_token: other.refresh_token,
+        };
+        let ret = signout_handler(
+            Extension(user.clone()),
+            State(state.clone()),
+            TypedHeader(bearer.clone()),
+            Some(Json(other.clone())),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+        assert!(state
+            .rotate_refresh_token(&other.refresh_token)
+            .await
+            .is_ok());
+
+        let ret = signout_handler(
+            Extension(user),
+            State(state.clone()),
+            TypedHeader(bearer),
+            Some(Json(input.clone())),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+
+        let err = state.verify(&output.token).await.unwrap_err();
+        assert!(matches!(err, AppError::Unauthorized(_)));
+        let err = state
+            .rotate_refresh_token(&input.refresh_token)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::Unauthorized(_)));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn signin_with_non_exist_user_should_403() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
@@ -123,4 +276,22 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn jwks_should_verify_issued_tokens() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let ret = jwks_handler(State(state.clone())).await.into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let jwks: Jwks = serde_json::from_slice(&body)?;
+        assert_eq!(jwks.keys.len(), state.config.auth.keys.len());
+
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let token = state.ek.sign(user)?;
+        let kid = DecodingKey::token_kid(&token);
+        assert_eq!(kid.as_ref(), Some(&state.config.auth.current));
+        let dk = DecodingKey::from_jwks(&jwks)?;
+        assert_eq!(dk.verify(&token)?.id, 1);
+        Ok(())
+    }
 }
@@ -2,7 +2,7 @@ use axum::extract::Path;
 use axum::{extract::State, http::StatusCode, response::IntoResponse, Extension, Json};
 use chat_core::User;
 
-use crate::models::CreateChat;
+use crate::models::{AddChatMembers, CreateChat, MarkRead, UpdateChat};
 use crate::{error::AppError, AppState};
 
 #[utoipa::path(
@@ -19,7 +19,7 @@ pub(crate) async fn list_chat_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
 ) -> Result<impl IntoResponse, AppError> {
-    let chats = state.fetch_chats(user.ws_id as _).await?;
+    let chats = state.fetch_chats(user.ws_id as _, user.id as _).await?;
     Ok((StatusCode::OK, Json(chats)))
 }
 
@@ -28,6 +28,7 @@ pub(crate) async fn list_chat_handler(
     path = "/api/chats",
     responses(
         (status = 201, description = "Chat created", body = Chat),
+        (status = 403, description = "Guests can't create public channels", body = ErrorOutput),
     ),
     security(
         ("token" = [])
@@ -38,7 +39,7 @@ pub(crate) async fn create_chat_handler(
     State(state): State<AppState>,
     Json(input): Json<CreateChat>,
 ) -> Result<impl IntoResponse, AppError> {
-    let chat = state.create_chat(input, user.ws_id as _).await?;
+    let chat = state.create_chat(input, user.role, user.ws_id as _).await?;
     Ok((StatusCode::CREATED, Json(chat)))
 }
 
@@ -57,20 +58,219 @@ pub(crate) async fn create_chat_handler(
     )
 )]
 pub(crate) async fn get_chat_handler(
+    Extension(user): Extension<User>,
     State(state): State<AppState>,
     Path(id): Path<u64>,
 ) -> Result<impl IntoResponse, AppError> {
-    let chat = state.get_chat_by_id(id as _).await?;
+    let chat = state.get_chat_by_id(id as _, user.ws_id as _).await?;
     match chat {
         Some(chat) => Ok(Json(chat)),
         None => Err(AppError::NotFound(format!("chat id {id} not found"))),
     }
 }
 
-pub(crate) async fn update_chat_handler() -> impl IntoResponse {
-    "update chat"
+#[utoipa::path(
+    patch,
+    path = "/api/chats/{id}",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    request_body = UpdateChat,
+    responses(
+        (status = 200, description = "Chat updated", body = Chat),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 403, description = "Guests can't make a chat public, only admins may remove others", body = ErrorOutput),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<UpdateChat>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state
+        .update_chat_by_id(id, input, user.id as _, user.role, user.ws_id as _)
+        .await?;
+    Ok(Json(chat))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 204, description = "Chat deleted"),
+        (status = 403, description = "Only admins may delete groups and channels", body = ErrorOutput),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn delete_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .delete_chat_by_id(id, user.role, user.ws_id as _)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/members",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    request_body = AddChatMembers,
+    responses(
+        (status = 200, description = "Members added", body = Chat),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn add_chat_members_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<AddChatMembers>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state.add_chat_members(id, input, user.ws_id as _).await?;
+    Ok(Json(chat))
 }
 
-pub(crate) async fn delete_chat_handler() -> impl IntoResponse {
-    "delete chat"
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/members/{user_id}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("user_id" = u64, Path, description = "User id")
+    ),
+    responses(
+        (status = 200, description = "Member removed", body = Chat),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 403, description = "Only admins may remove others", body = ErrorOutput),
+        (status = 404, description = "Chat or member not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn remove_chat_member_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, user_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state
+        .remove_chat_member(id, user_id, user.id as _, user.role, user.ws_id as _)
+        .await?;
+    Ok(Json(chat))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/channels",
+    responses(
+        (status = 200, description = "Public channels of the workspace", body = Vec<PublicChannel>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_channels_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let channels = state
+        .fetch_public_channels(user.ws_id as _, user.id as _)
+        .await?;
+    Ok(Json(channels))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/join",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "Joined the channel", body = Chat),
+        (status = 403, description = "Not a public channel", body = ErrorOutput),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn join_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state.join_chat(id, user.id as _, user.ws_id as _).await?;
+    Ok(Json(chat))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/leave",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "Left the chat", body = Chat),
+        (status = 400, description = "Too few members would be left", body = ErrorOutput),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn leave_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state
+        .remove_chat_member(id, user.id as _, user.id as _, user.role, user.ws_id as _)
+        .await?;
+    Ok(Json(chat))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/read",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    request_body = MarkRead,
+    responses(
+        (status = 200, description = "Read position of the caller", body = ChatRead),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn mark_read_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<MarkRead>,
+) -> Result<impl IntoResponse, AppError> {
+    let read = state
+        .mark_read(input, id, user.id as _, user.ws_id as _)
+        .await?;
+    Ok(Json(read))
 }
@@ -0,0 +1,396 @@
+use std::{ops::Bound, path::PathBuf, str::FromStr, time::Duration};
+
+use axum::{
+    body::Body,
+    extract::{multipart::MultipartError, Multipart, Path, Query, State},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use axum_extra::{
+    headers::{AcceptRanges, CacheControl, ContentLength, ContentRange, ETag, HeaderMapExt, IfNoneMatch, Range},
+    TypedHeader,
+};
+use chat_core::User;
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use tokio::{fs, io::AsyncWriteExt};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{error::AppError, storage::FileStore, thumbnail::image_dimensions, AppState, ChatFile, CollectFiles};
+
+// file urls are content addressed, what's behind one never changes
+const FILE_MAX_AGE: Duration = Duration::from_secs(365 * 24 * 3600);
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct FileParams {
+    /// filename offered to the browser, defaults to the name it was uploaded with
+    name: Option<String>,
+    /// serve the smallest thumbnail at least this large, the original if there's none
+    size: Option<u32>,
+}
+
+pub(crate) async fn file_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((ws_id, path)): Path<(i64, String)>,
+    Query(params): Query<FileParams>,
+    range: Option<TypedHeader<Range>>,
+    if_none_match: Option<TypedHeader<IfNoneMatch>>,
+) -> Result<Response, AppError> {
+    if user.ws_id != ws_id {
+        return Err(AppError::NotFound(
+            "File does not exist or you don't have permission".to_string(),
+        ));
+    }
+    let file = ChatFile::from_str(&format!("/files/{}/{}", ws_id, path))
+        .map_err(|_| AppError::NotFound("File does not exist".to_string()))?;
+    let sizes = &state.config.server.upload.thumbnail_sizes;
+    let mut thumbnail = None;
+    if let Some(&size) = params
+        .size
+        .and_then(|size| sizes.iter().filter(|&&v| v >= size).min())
+    {
+        if state.store.head(&file.thumbnail_key(size)).await?.is_some() {
+            thumbnail = Some(size);
+        }
+    }
+    let (key, etag) = match thumbnail {
+        Some(size) => (file.thumbnail_key(size), format!("\"{}_{}\"", file.hash, size)),
+        None => (file.key(), format!("\"{}\"", file.hash)),
+    };
+    let etag: ETag = etag.parse().expect("a hex hash is a valid etag");
+
+    let mut res = Response::builder()
+        .body(Body::empty())
+        .expect("empty response");
+    let headers = res.headers_mut();
+    headers.typed_insert(etag.clone());
+    if params.size.is_some() && thumbnail.is_none() {
+        // the thumbnail may not be made yet
+        headers.typed_insert(CacheControl::new().with_private().with_no_cache());
+    } else {
+        headers.typed_insert(
+            CacheControl::new()
+                .with_private()
+                .with_max_age(FILE_MAX_AGE)
+                .with_immutable(),
+        );
+    }
+    // only what exists may be not modified
+    let Some(meta) = state.store.head(&key).await? else {
+        return Err(AppError::NotFound("File does not exist".to_string()));
+    };
+    if let Some(TypedHeader(if_none_match)) = if_none_match {
+        if !if_none_match.precondition_passes(&etag) {
+            *res.status_mut() = StatusCode::NOT_MODIFIED;
+            return Ok(res);
+        }
+    }
+
+    info!("Serving file: {}", key);
+    let mime = match thumbnail {
+        Some(_) => "image/webp".to_string(),
+        None => mime_guess::from_path(&path)
+            .first_or_octet_stream()
+            .to_string(),
+    };
+    let name = match params.name {
+        Some(name) => name,
+        None => match state.find_file_info(&file).await? {
+            Some(info) => info.name,
+            // uploaded before file names were recorded
+            None => path.rsplit('/').next().unwrap_or_default().to_string(),
+        },
+    };
+    let headers = res.headers_mut();
+    let name = match thumbnail {
+        Some(_) => format!(
+            "{}.webp",
+            name.rsplit_once('.')
+                .map_or(name.as_str(), |(stem, _)| stem)
+        ),
+        None => name,
+    };
+    headers.insert(header::CONTENT_TYPE, mime.parse().unwrap());
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    headers.insert(header::CONTENT_DISPOSITION, content_disposition(&name, &mime));
+    headers.typed_insert(AcceptRanges::bytes());
+
+    let range = match range {
+        Some(TypedHeader(range)) => match byte_range(&range, meta.size) {
+            Ok(range) => range,
+            Err(_) => {
+                *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+                res.headers_mut()
+                    .typed_insert(ContentRange::unsatisfied_bytes(meta.size));
+                return Ok(res);
+            }
+        },
+        None => None,
+    };
+    let stream = state.store.stream(&key, range).await?;
+    let headers = res.headers_mut();
+    match range {
+        Some((start, end)) => {
+            headers.typed_insert(ContentLength(end - start + 1));
+            headers.typed_insert(ContentRange::bytes(start..=end, meta.size).expect("range is within the file"));
+            *res.status_mut() = StatusCode::PARTIAL_CONTENT;
+        }
+        None => headers.typed_insert(ContentLength(meta.size)),
+    }
+    *res.body_mut() = Body::from_stream(stream);
+    Ok(res)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/files/{id}/meta",
+    params(
+        ("id" = u64, Path, description = "File id")
+    ),
+    responses(
+        (status = 200, description = "File metadata", body = FileInfo),
+        (status = 404, description = "File not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn file_meta_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let info = state.get_file_info(id, user.ws_id as _).await?;
+    Ok(Json(info))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/files/gc",
+    request_body = CollectFiles,
+    responses(
+        (status = 200, description = "Files removed, or which would be on a dry run", body = FileGcReport),
+        (status = 403, description = "Not an owner or admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn collect_files_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CollectFiles>,
+) -> Result<impl IntoResponse, AppError> {
+    let grace_period = Duration::from_secs(state.config.server.file_gc.grace_period);
+    let report = state
+        .collect_files(Some(user.ws_id as _), grace_period, input.dry_run)
+        .await?;
+    Ok(Json(report))
+}
+
+pub(crate) async fn upload_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, AppError> {
+    let ws_id = user.ws_id as u64;
+    let config = &state.config.server.upload;
+    let mut files = vec![];
+    while let Some(mut field) = multipart.next_field().await.map_err(multipart_error)? {
+        let Some(filename) = field.file_name().map(|name| name.to_string()) else {
+            warn!("Skipping multipart field {:?} without a file name", field.name());
+            continue;
+        };
+        // a generic type says nothing, the extension is a better guess then
+        let mime = field
+            .content_type()
+            .filter(|v| *v != "application/octet-stream")
+            .map(|v| v.to_string())
+            .unwrap_or_else(|| {
+                mime_guess::from_path(&filename)
+                    .first_or_octet_stream()
+                    .to_string()
+            });
+        if !config.accepts(&filename, &mime) {
+            return Err(AppError::UnsupportedMediaType(format!("{filename} ({mime})")));
+        }
+
+        // received into a temp file and hashed on the way, the content addresses it
+        let tmp = TempFile::new(&state.config.server.base_dir).await?;
+        let mut out = fs::File::create(&tmp.0).await?;
+        let mut hasher = Sha1::new();
+        let mut size = 0;
+        while let Some(chunk) = field.chunk().await.map_err(multipart_error)? {
+            size += chunk.len() as u64;
+            if size > config.max_file_size {
+                return Err(AppError::PayloadTooLarge(format!(
+                    "{filename} is larger than {} bytes",
+                    config.max_file_size
+                )));
+            }
+            hasher.update(&chunk);
+            out.write_all(&chunk).await?;
+        }
+        out.flush().await?;
+        drop(out);
+
+        let file = ChatFile::from_hash(ws_id, &filename, hex::encode(hasher.finalize()));
+        let dimensions = match mime.starts_with("image/") {
+            true => image_dimensions(tmp.0.clone()).await,
+            false => None,
+        };
+        // files are content addressed, the same content is only stored once
+        state
+            .create_file(&file, tmp.0.as_path(), &filename, &mime, dimensions, user.id as _)
+            .await?;
+        // the metadata is at /files/:id/meta, and on the messages the urls are sent with
+        files.push(file.url());
+        if dimensions.is_some() {
+            state.thumbnailer.enqueue(file);
+        }
+    }
+    Ok(Json(files))
+}
+
+fn multipart_error(e: MultipartError) -> AppError {
+    match e.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => AppError::PayloadTooLarge(e.body_text()),
+        _ => AppError::InvalidUpload(e.body_text()),
+    }
+}
+
+/// an upload being received, removed unless the store took it
+struct TempFile(PathBuf);
+
+impl TempFile {
+    async fn new(base_dir: &std::path::Path) -> Result<Self, AppError> {
+        let dir = base_dir.join(".uploads");
+        fs::create_dir_all(&dir).await?;
+        Ok(Self(dir.join(Uuid::now_v7().to_string())))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// the inclusive byte range to serve. only single ranges are served, for anything
+/// else the whole file is sent which is allowed by RFC 9110.
+fn byte_range(range: &Range, len: u64) -> Result<Option<(u64, u64)>, ()> {
+    let ranges: Vec<_> = range.satisfiable_ranges(len).collect();
+    let [(start, end)] = ranges[..] else {
+        return if ranges.is_empty() { Err(()) } else { Ok(None) };
+    };
+    let start = match start {
+        Bound::Included(v) => v,
+        Bound::Excluded(v) => v + 1,
+        Bound::Unbounded => 0,
+    };
+    let end = match end {
+        Bound::Included(v) => v.min(len.saturating_sub(1)),
+        Bound::Excluded(v) => v.min(len).saturating_sub(1),
+        Bound::Unbounded => len.saturating_sub(1),
+    };
+    if start >= len || start > end {
+        return Err(());
+    }
+    Ok(Some((start, end)))
+}
+
+/// `inline` so media plays in the browser, `attachment` for anything that could run script in
+/// our origin (html, svg, ...). the name is given as ascii and RFC 5987 utf-8
+fn content_disposition(name: &str, mime: &str) -> HeaderValue {
+    let disposition = if is_inline(mime) { "inline" } else { "attachment" };
+    let ascii: String = name
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    let encoded: String = name
+        .bytes()
+        .map(|b| match b {
+            b'a'..=b'z'
+            | b'A'..=b'Z'
+            | b'0'..=b'9'
+            | b'!'
+            | b'#'
+            | b'$'
+            | b'&'
+            | b'+'
+            | b'-'
+            | b'.'
+            | b'^'
+            | b'_'
+            | b'`'
+            | b'|'
+            | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect();
+    HeaderValue::from_str(&format!(
+        "{disposition}; filename=\"{ascii}\"; filename*=UTF-8''{encoded}"
+    ))
+    .expect("only visible ascii is used")
+}
+
+/// media the browser renders without running anything
+fn is_inline(mime: &str) -> bool {
+    match mime.split_once('/') {
+        Some(("image", subtype)) => subtype != "svg+xml",
+        Some(("audio" | "video", _)) => true,
+        _ => mime == "application/pdf",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum_extra::headers::Header;
+
+    use super::*;
+
+    fn range(value: &'static str) -> Range {
+        Range::decode(&mut std::iter::once(&HeaderValue::from_static(value))).unwrap()
+    }
+
+    #[test]
+    fn byte_range_should_work() {
+        assert_eq!(byte_range(&range("bytes=0-4"), 10), Ok(Some((0, 4))));
+        assert_eq!(byte_range(&range("bytes=5-100"), 10), Ok(Some((5, 9))));
+        assert_eq!(byte_range(&range("bytes=3-"), 10), Ok(Some((3, 9))));
+        assert_eq!(byte_range(&range("bytes=-4"), 10), Ok(Some((6, 9))));
+        assert_eq!(byte_range(&range("bytes=0-1,3-4"), 10), Ok(None));
+        assert_eq!(byte_range(&range("bytes=10-20"), 10), Err(()));
+        assert_eq!(byte_range(&range("bytes=0-0"), 0), Err(()));
+    }
+
+    #[test]
+    fn content_disposition_should_work() {
+        assert_eq!(
+            content_disposition("a b.png", "image/png"),
+            "inline; filename=\"a b.png\"; filename*=UTF-8''a%20b.png"
+        );
+        assert_eq!(
+            content_disposition("résumé.pdf", "application/pdf"),
+            "inline; filename=\"r_sum_.pdf\"; filename*=UTF-8''r%C3%A9sum%C3%A9.pdf"
+        );
+        // anything that may run script is downloaded
+        for (name, mime) in [
+            ("a.html", "text/html"),
+            ("a.svg", "image/svg+xml"),
+            ("a.txt", "text/plain"),
+            ("a.bin", "application/octet-stream"),
+        ] {
+            assert_eq!(
+                content_disposition(name, mime),
+                format!("attachment; filename=\"{name}\"; filename*=UTF-8''{name}").as_str()
+            );
+        }
+    }
+}
@@ -1,14 +1,12 @@
 use axum::{
-    extract::{Multipart, Path, Query, State},
-    http::{HeaderMap, StatusCode},
+    extract::{Path, Query, State},
+    http::StatusCode,
     response::IntoResponse,
     Extension, Json,
 };
 use chat_core::User;
-use tokio::fs;
-use tracing::{info, warn};
 
-use crate::{error::AppError, AppState, ChatFile, CreateMessage, ListMessages};
+use crate::{error::AppError, AppState, CreateMessage, ListMessages, SearchMessages, UpdateMessage};
 
 pub(crate) async fn send_message_handler(
     Extension(user): Extension<User>,
@@ -16,7 +14,9 @@ pub(crate) async fn send_message_handler(
     Path(id): Path<u64>,
     Json(input): Json<CreateMessage>,
 ) -> Result<impl IntoResponse, AppError> {
-    let msg = state.create_message(input, id, user.id as _).await?;
+    let msg = state
+        .create_message(input, id, user.id as _, user.ws_id as _)
+        .await?;
     Ok((StatusCode::CREATED, Json(msg)))
 }
 #[utoipa::path(
@@ -35,63 +35,175 @@ pub(crate) async fn send_message_handler(
     )
 )]
 pub(crate) async fn list_message_handler(
+    Extension(user): Extension<User>,
     State(state): State<AppState>,
     Path(id): Path<u64>,
     Query(input): Query<ListMessages>,
 ) -> Result<impl IntoResponse, AppError> {
-    let messages = state.list_message(input, id).await?;
+    let messages = state
+        .list_message(input, id, user.id as _, user.ws_id as _)
+        .await?;
+    Ok(Json(messages))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/messages/{mid}/replies",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("mid" = u64, Path, description = "Parent message id"),
+        ListMessages
+    ),
+    responses(
+        (status = 200, description = "Replies to the message", body = Vec<Message>),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_replies_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(u64, u64)>,
+    Query(input): Query<ListMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    let messages = state
+        .list_replies(input, id, mid, user.id as _, user.ws_id as _)
+        .await?;
     Ok(Json(messages))
 }
 
-pub(crate) async fn file_handler(
+#[utoipa::path(
+    patch,
+    path = "/api/chats/{id}/messages/{mid}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("mid" = u64, Path, description = "Message id"),
+    ),
+    request_body = UpdateMessage,
+    responses(
+        (status = 200, description = "Message updated", body = Message),
+        (status = 403, description = "Not the sender or a workspace owner/admin", body = ErrorOutput),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(u64, u64)>,
+    Json(input): Json<UpdateMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let msg = state
+        .update_message(input, id, mid, user.id as _, user.role, user.ws_id as _)
+        .await?;
+    Ok(Json(msg))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/messages/{mid}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("mid" = u64, Path, description = "Message id"),
+    ),
+    responses(
+        (status = 204, description = "Message deleted"),
+        (status = 403, description = "Not the sender or a workspace owner/admin", body = ErrorOutput),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn delete_message_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
-    Path((ws_id, path)): Path<(i64, String)>,
+    Path((id, mid)): Path<(u64, u64)>,
 ) -> Result<impl IntoResponse, AppError> {
-    if user.ws_id != ws_id {
-        return Err(AppError::NotFound(
-            "File does not exist or you don't have permission".to_string(),
-        ));
-    }
-    let base_dir = state.config.server.base_dir.join(ws_id.to_string());
-    let path = base_dir.join(path);
-    info!("Serving file: {}", path.display());
-    if !path.exists() {
-        return Err(AppError::NotFound("File does not exist".to_string()));
-    }
-    let mime = mime_guess::from_path(&path).first_or_octet_stream();
-    //TODO: streaming
-    let body = fs::read(path).await?;
-    let mut headers = HeaderMap::new();
-    headers.insert("Content-Type", mime.to_string().parse().unwrap());
+    state
+        .delete_message(id, mid, user.id as _, user.role, user.ws_id as _)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
 
-    Ok((headers, body))
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/messages/{mid}/reactions/{emoji}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("mid" = u64, Path, description = "Message id"),
+        ("emoji" = String, Path, description = "Emoji to react with"),
+    ),
+    responses(
+        (status = 200, description = "Reactions of the message", body = Vec<Reaction>),
+        (status = 400, description = "Invalid emoji", body = ErrorOutput),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn add_reaction_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid, emoji)): Path<(u64, u64, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    let reactions = state
+        .add_reaction(id, mid, &emoji, user.id as _, user.ws_id as _)
+        .await?;
+    Ok(Json(reactions))
 }
 
-pub(crate) async fn upload_handler(
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/messages/{mid}/reactions/{emoji}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("mid" = u64, Path, description = "Message id"),
+        ("emoji" = String, Path, description = "Emoji to take back"),
+    ),
+    responses(
+        (status = 200, description = "Reactions of the message", body = Vec<Reaction>),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn remove_reaction_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
-    mut multipart: Multipart,
+    Path((id, mid, emoji)): Path<(u64, u64, String)>,
 ) -> Result<impl IntoResponse, AppError> {
-    let ws_id = user.ws_id as u64;
-    let base_dir = state.config.server.base_dir.join(ws_id.to_string());
-    let mut files = vec![];
-    while let Some(filed) = multipart.next_field().await.unwrap() {
-        let filename = filed.file_name().map(|name| name.to_string());
-        let (Some(filename), Ok(data)) = (filename, filed.bytes().await) else {
-            warn!("Failed to read mutipart filed");
-            continue;
-        };
-        let file = ChatFile::new(ws_id, &filename, &data);
-        let path = file.path(&base_dir);
-        if path.exists() {
-            info!("File {} already exists {:?}", filename, path);
-        } else {
-            fs::create_dir_all(path.parent().expect("file path parent should exist")).await?;
-            fs::write(path, data).await?;
-        }
+    let reactions = state
+        .remove_reaction(id, mid, &emoji, user.id as _, user.ws_id as _)
+        .await?;
+    Ok(Json(reactions))
+}
 
-        files.push(file);
-    }
-    Ok(Json(files))
+#[utoipa::path(
+    get,
+    path = "/api/search",
+    params(SearchMessages),
+    responses(
+        (status = 200, description = "Matching messages, newest first", body = Vec<SearchHit>),
+        (status = 400, description = "Invalid query", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn search_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<SearchMessages>,
+) -> Result<impl IntoResponse, AppError> {
+    let hits = state
+        .search_messages(input, user.id as _, user.ws_id as _)
+        .await?;
+    Ok(Json(hits))
 }
@@ -1,7 +1,12 @@
-use axum::{extract::State, response::IntoResponse, Extension, Json};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
 use chat_core::User;
 
-use crate::{error::AppError, AppState};
+use crate::{error::AppError, AppState, CreateInvite, JoinWorkspace, UpdateWorkspace};
 
 pub(crate) async fn list_chat_users_handler(
     Extension(user): Extension<User>,
@@ -12,3 +17,266 @@ pub(crate) async fn list_chat_users_handler(
         .await?;
     Ok(Json(users))
 }
+
+#[utoipa::path(
+    post,
+    path = "/api/invites",
+    request_body = CreateInvite,
+    responses(
+        (status = 201, description = "Invite created, the token is only returned here", body = Invite),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 403, description = "Not an owner or admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn create_invite_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateInvite>,
+) -> Result<impl IntoResponse, AppError> {
+    let invite = state
+        .create_invite(input, user.ws_id as _, user.id as _)
+        .await?;
+    Ok((StatusCode::CREATED, Json(invite)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/invites",
+    responses(
+        (status = 200, description = "Invites which can still be used", body = Vec<Invite>),
+        (status = 403, description = "Not an owner or admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_invites_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let invites = state.list_invites(user.ws_id as _).await?;
+    Ok(Json(invites))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/invites/{id}",
+    params(
+        ("id" = u64, Path, description = "Invite id")
+    ),
+    responses(
+        (status = 204, description = "Invite revoked"),
+        (status = 403, description = "Not an owner or admin", body = ErrorOutput),
+        (status = 404, description = "Invite not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn revoke_invite_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.revoke_invite(id, user.ws_id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/workspaces",
+    responses(
+        (status = 200, description = "Workspaces of the user", body = Vec<UserWorkspace>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_workspaces_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let workspaces = state.list_user_workspaces(user.id as _).await?;
+    Ok(Json(workspaces))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/workspaces/{id}/switch",
+    params(
+        ("id" = u64, Path, description = "Workspace id")
+    ),
+    responses(
+        (status = 200, description = "Tokens scoped to the workspace", body = AuthOutput),
+        (status = 404, description = "Not a member of the workspace", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn switch_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = state.switch_workspace(user.id as _, id).await?;
+    Ok(Json(state.auth_output(user).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/workspaces/join",
+    request_body = JoinWorkspace,
+    responses(
+        (status = 200, description = "Tokens scoped to the joined workspace", body = AuthOutput),
+        (status = 403, description = "Invalid or expired invite", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn join_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<JoinWorkspace>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = state
+        .join_workspace(&input.invite, user.id as _, &user.email)
+        .await?;
+    Ok(Json(state.auth_output(user).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/workspace",
+    responses(
+        (status = 200, description = "The workspace of the token", body = Workspace),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn get_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .find_workspace_by_id(user.ws_id as _)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("workspace id {} not found", user.ws_id)))?;
+    Ok(Json(ws))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/workspace",
+    request_body = UpdateWorkspace,
+    responses(
+        (status = 200, description = "Workspace updated", body = Workspace),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 403, description = "Not an owner or admin, or not the owner for a transfer", body = ErrorOutput),
+        (status = 409, description = "Name taken by another workspace", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateWorkspace>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .update_workspace(user.ws_id as _, input, user.role)
+        .await?;
+    Ok(Json(ws))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/workspace",
+    responses(
+        (status = 204, description = "Workspace deleted with its chats, messages and files"),
+        (status = 400, description = "A member has no other workspace", body = ErrorOutput),
+        (status = 403, description = "Not the owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn delete_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    state.delete_workspace(user.ws_id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/workspace/members",
+    responses(
+        (status = 200, description = "Members with their roles", body = Vec<WorkspaceMember>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_members_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let members = state.list_members(user.ws_id as _).await?;
+    Ok(Json(members))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/workspace/members/{id}/deactivate",
+    params(
+        ("id" = u64, Path, description = "User id")
+    ),
+    responses(
+        (status = 204, description = "Member deactivated, their tokens are revoked"),
+        (status = 403, description = "Not an owner or admin, or deactivating the owner", body = ErrorOutput),
+        (status = 404, description = "Active member not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn deactivate_member_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.deactivate_member(user.ws_id as _, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/workspace/members/{id}/reactivate",
+    params(
+        ("id" = u64, Path, description = "User id")
+    ),
+    responses(
+        (status = 204, description = "Member reactivated"),
+        (status = 403, description = "Not an owner or admin", body = ErrorOutput),
+        (status = 404, description = "Deactivated member not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn reactivate_member_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.reactivate_member(user.ws_id as _, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -4,32 +4,45 @@ mod handlers;
 mod middlewares;
 mod models;
 mod openapi;
+mod storage;
+mod thumbnail;
 use core::fmt;
-use std::{ops::Deref, sync::Arc};
+use std::{ops::Deref, sync::Arc, time::Duration};
 
 use anyhow::Context;
 use axum::{
-    middleware::from_fn_with_state,
-    routing::{get, post},
+    extract::DefaultBodyLimit,
+    handler::Handler,
+    middleware::{from_fn, from_fn_with_state},
+    routing::{delete, get, patch, post},
     Router,
 };
 use chat_core::{
     middlewares::{set_layer, verify_token, TokenVerify},
-    DecodingKey, EncodingKey,
+    DecodingKey, EncodingKey, Permission,
 };
 pub use config::AppConfig;
 use error::AppError;
 use error::ErrorOutput;
 use handlers::{
-    create_chat_handler, delete_chat_handler, file_handler, get_chat_handler, index_handler, list_chat_handler,
-    list_chat_users_handler, list_message_handler, send_message_handler, signin_handler, signup_handler,
-    update_chat_handler, upload_handler,
+    add_chat_members_handler, add_reaction_handler, collect_files_handler, create_chat_handler, create_invite_handler,
+    deactivate_member_handler, delete_chat_handler, delete_message_handler, delete_workspace_handler, file_handler,
+    file_meta_handler, get_chat_handler, get_workspace_handler, index_handler, join_chat_handler,
+    join_workspace_handler, jwks_handler, leave_chat_handler, list_channels_handler, list_chat_handler,
+    list_chat_users_handler, list_invites_handler, list_members_handler, list_message_handler, list_replies_handler,
+    list_workspaces_handler, mark_read_handler, reactivate_member_handler, refresh_handler, remove_chat_member_handler,
+    remove_reaction_handler, revoke_invite_handler, search_handler, send_message_handler, signin_handler,
+    signout_handler, signup_handler, switch_workspace_handler, update_chat_handler, update_message_handler,
+    update_workspace_handler, upload_handler,
 };
-use middlewares::verify_chat;
+use middlewares::{require_permission, verify_chat};
 pub use models::*;
 use openapi::OpenApiRouter;
 use sqlx::PgPool;
+use storage::Storage;
+use thumbnail::Thumbnailer;
 use tokio::fs;
+use tracing::{info, warn};
 
 #[derive(Debug, Clone)]
 pub struct AppState {
@@ -42,6 +55,8 @@ pub struct AppStateInner {
     pub(crate) dk: DecodingKey,
     pub(crate) ek: EncodingKey,
     pub(crate) pool: PgPool,
+    pub(crate) store: Arc<Storage>,
+    pub(crate) thumbnailer: Thumbnailer,
 }
 impl Deref for AppState {
     type Target = AppStateInner;
@@ -56,13 +71,21 @@ impl AppState {
         let _ = fs::create_dir_all(&config.server.base_dir)
             .await
             .context("create base_dir failed");
-        let dk = DecodingKey::load(&config.auth.pk).context("load pk failed")?;
-        let ek = EncodingKey::load(&config.auth.sk).context("load sk failed")?;
+        let (ek, dk) = config.auth.load_keys()?;
+        let store = Arc::new(Storage::new(&config.server)?);
+        let thumbnailer = Thumbnailer::new(store.clone(), config.server.upload.thumbnail_sizes.clone());
         let pool = PgPool::connect(&config.server.db_url)
             .await
             .context("connect to db failed")?;
         Ok(Self {
-            inner: Arc::new(AppStateInner { config, dk, ek, pool }),
+            inner: Arc::new(AppStateInner {
+                config,
+                dk,
+                ek,
+                pool,
+                store,
+                thumbnailer,
+            }),
         })
     }
 }
@@ -85,39 +108,151 @@ pub async fn get_router(state: AppState) -> Result<Router, AppError> {
                 .post(send_message_handler),
         )
         .route("/:id/messages", get(list_message_handler))
+        .route(
+            "/:id/messages/:mid",
+            patch(update_message_handler).delete(delete_message_handler),
+        )
+        .route("/:id/messages/:mid/replies", get(list_replies_handler))
+        .route(
+            "/:id/messages/:mid/reactions/:emoji",
+            post(add_reaction_handler).delete(remove_reaction_handler),
+        )
+        .route("/:id/read", post(mark_read_handler))
+        .route("/:id/members", post(add_chat_members_handler))
+        .route("/:id/members/:user_id", delete(remove_chat_member_handler))
+        .route("/:id/leave", post(leave_chat_handler))
         .layer(from_fn_with_state(state.clone(), verify_chat))
+        // not a member yet, `join_chat` checks the chat itself
+        .route("/:id/join", post(join_chat_handler))
         .route("/", get(list_chat_handler).post(create_chat_handler));
 
+    let invites = Router::new()
+        .route("/", get(list_invites_handler).post(create_invite_handler))
+        .route("/:id", delete(revoke_invite_handler))
+        .layer(from_fn(|req, next| {
+            require_permission(Permission::ManageInvites, req, next)
+        }));
+
+    // member administration first, the layer only covers the routes before it
+    let workspace = Router::new()
+        .route("/members/:id/deactivate", post(deactivate_member_handler))
+        .route("/members/:id/reactivate", post(reactivate_member_handler))
+        .route_layer(from_fn(|req, next| {
+            require_permission(Permission::ManageMembers, req, next)
+        }))
+        .route("/members", get(list_members_handler))
+        .route(
+            "/",
+            get(get_workspace_handler)
+                .patch(update_workspace_handler.layer(from_fn(|req, next| {
+                    require_permission(Permission::ManageWorkspace, req, next)
+                })))
+                .delete(delete_workspace_handler.layer(from_fn(|req, next| {
+                    require_permission(Permission::DeleteWorkspace, req, next)
+                }))),
+        );
+
     let api = Router::new()
         .route("/users", get(list_chat_users_handler))
         .nest("/chats", chat)
-        .route("/upload", post(upload_handler))
+        .route("/channels", get(list_channels_handler))
+        .route("/search", get(search_handler))
+        .route(
+            "/upload",
+            post(upload_handler).layer(DefaultBodyLimit::max(state.config.server.upload.max_request_size as _)),
+        )
         .route("/files/:ws_id/*path", get(file_handler))
+        .route("/files/:id/meta", get(file_meta_handler))
+        .route(
+            "/files/gc",
+            post(collect_files_handler).layer(from_fn(|req, next| {
+                require_permission(Permission::ManageFiles, req, next)
+            })),
+        )
+        .nest("/invites", invites)
+        .nest("/workspace", workspace)
+        .route("/workspaces", get(list_workspaces_handler))
+        .route("/workspaces/join", post(join_workspace_handler))
+        .route("/workspaces/:id/switch", post(switch_workspace_handler))
+        .route("/signout", post(signout_handler))
         .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
         // routes doesn't need token verification
         .route("/signin", post(signin_handler))
+        .route("/refresh", post(refresh_handler))
         .route("/signup", post(signup_handler));
 
     let app = Router::new()
         .openapi()
         .route("/", get(index_handler))
+        .route("/.well-known/jwks.json", get(jwks_handler))
         .nest("/api", api)
         .with_state(state);
 
     Ok(set_layer(app))
 }
 
+/// collect the unused files of all workspaces every `file_gc.interval` seconds, to be
+/// started once per process
+pub fn spawn_file_gc(state: AppState) {
+    let config = &state.config.server.file_gc;
+    if config.interval == 0 {
+        return;
+    }
+    let period = Duration::from_secs(config.interval);
+    let grace_period = Duration::from_secs(config.grace_period);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            match state.collect_files(None, grace_period, false).await {
+                Ok(report) if !report.files.is_empty() => {
+                    info!("file gc removed {} files, {} bytes", report.files.len(), report.bytes)
+                }
+                Ok(_) => {}
+                Err(e) => warn!("file gc failed: {}", e),
+            }
+        }
+    });
+}
+
+/// purge messages past the retention period of their workspace every `retention.interval`
+/// seconds, to be started once per process. the files they leave unused go with the next file gc.
+pub fn spawn_message_retention(state: AppState) {
+    let config = &state.config.server.retention;
+    if config.interval == 0 {
+        return;
+    }
+    let period = Duration::from_secs(config.interval);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            match state.purge_expired_messages().await {
+                Ok(0) => {}
+                Ok(n) => info!("retention removed {} messages", n),
+                Err(e) => warn!("message retention failed: {}", e),
+            }
+        }
+    });
+}
+
 impl TokenVerify for AppState {
     type Error = AppError;
 
-    fn verify(&self, token: &str) -> Result<chat_core::User, Self::Error> {
-        Ok(self.dk.verify(token)?)
+    async fn verify(&self, token: &str) -> Result<chat_core::User, Self::Error> {
+        let claims = self.dk.decode(token)?;
+        if self
+            .is_token_revoked(claims.jwt_id.as_deref(), &claims.custom)
+            .await?
+        {
+            return Err(AppError::Unauthorized("token has been revoked".to_string()));
+        }
+        Ok(claims.custom)
     }
 }
 
 #[cfg(feature = "test-util")]
 mod test_util {
-    use chat_core::{DecodingKey, EncodingKey};
     use sqlx::Executor;
     use sqlx_db_tester::TestPg;
 
@@ -126,14 +261,22 @@ mod test_util {
     impl AppState {
         pub async fn new_for_test() -> Result<(TestPg, Self), AppError> {
             let config = AppConfig::load()?;
-            let dk = DecodingKey::load(&config.auth.pk).context("load pk failed")?;
-            let ek = EncodingKey::load(&config.auth.sk).context("load sk failed")?;
+            let (ek, dk) = config.auth.load_keys()?;
             let post = config.server.db_url.rfind('/').expect("invalid db_url");
             let server_url = &config.server.db_url[..post];
             // dbg!(&server_url);
             let (tdb, pool) = get_test_pool(Some(server_url)).await;
+            let store = Arc::new(Storage::new(&config.server)?);
+            let thumbnailer = Thumbnailer::new(store.clone(), config.server.upload.thumbnail_sizes.clone());
             let state = Self {
-                inner: Arc::new(AppStateInner { config, dk, ek, pool }),
+                inner: Arc::new(AppStateInner {
+                    config,
+                    dk,
+                    ek,
+                    pool,
+                    store,
+                    thumbnailer,
+                }),
             };
             Ok((tdb, state))
         }
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use chat_server::{get_router, AppConfig, AppState};
+use chat_server::{get_router, spawn_file_gc, spawn_message_retention, AppConfig, AppState};
 use tokio::net::TcpListener;
 use tracing::{info, level_filters::LevelFilter};
 use tracing_subscriber::{fmt::Layer, layer::SubscriberExt, util::SubscriberInitExt, Layer as _};
@@ -12,6 +12,12 @@ async fn main() -> Result<()> {
     let config = AppConfig::load()?;
     let addr = format!("0.0.0.0:{}", config.server.port);
     let state = AppState::try_new(config).await?;
+    let recorded = state.record_sent_files().await?;
+    if recorded > 0 {
+        info!("Recorded {recorded} files sent before uploads were recorded");
+    }
+    spawn_file_gc(state.clone());
+    spawn_message_retention(state.clone());
     let app = get_router(state).await?;
 
     let listener = TcpListener::bind(&addr).await?;
@@ -1,24 +1,40 @@
 use axum::{
     extract::{FromRequestParts, Path, Request, State},
+    http::Method,
     middleware::Next,
     response::{IntoResponse, Response},
 };
-use chat_core::User;
+use chat_core::{ChatType, User};
+use serde::Deserialize;
 
 use crate::{error::AppError, AppState};
 
+// routes under a chat may carry more path params (e.g. `/:id/members/:user_id`), only `id` matters here
+#[derive(Debug, Deserialize)]
+struct ChatPath {
+    id: u64,
+}
+
 pub async fn verify_chat(State(state): State<AppState>, req: Request, next: Next) -> Response {
     let (mut parts, body) = req.into_parts();
-    let Path(chat_id) = Path::<u64>::from_request_parts(&mut parts, &state)
-        .await
-        .unwrap();
+    let chat_id = match Path::<ChatPath>::from_request_parts(&mut parts, &state).await {
+        Ok(Path(path)) => path.id,
+        Err(e) => return e.into_response(),
+    };
     let user = parts.extensions.get::<User>().unwrap();
-    eprintln!("chat_id: {}", chat_id);
-    eprintln!("user: {:?}", user);
-    if !state
-        .is_chat_member(chat_id, user.id as _)
-        .await
-        .unwrap_or_default()
+    // chats of other workspaces are invisible to the user
+    let chat = match state.get_chat_by_id(chat_id, user.ws_id as _).await {
+        Ok(Some(chat)) => chat,
+        Ok(None) => return AppError::NotFound(format!("chat id {chat_id} not found")).into_response(),
+        Err(e) => return e.into_response(),
+    };
+    // anyone of the workspace may preview a public channel, read-only
+    let preview = chat.r#type == ChatType::PublicChannel && parts.method == Method::GET;
+    if !preview
+        && !state
+            .is_chat_member(chat_id, user.id as _, user.ws_id as _)
+            .await
+            .unwrap_or_default()
     {
         let err = AppError::CreateMessageError(format!("User {} are not a member of chat {chat_id}", user.id));
         return err.into_response();
@@ -32,7 +48,7 @@ pub async fn verify_chat(State(state): State<AppState>, req: Request, next: Next
 mod tests {
     use anyhow::Result;
     use axum::{body::Body, http::StatusCode, middleware::from_fn_with_state, routing::get, Router};
-    use chat_core::middlewares::verify_token;
+    use chat_core::{middlewares::verify_token, Role};
     use tower::ServiceExt;
 
     use super::*;
@@ -45,14 +61,12 @@ mod tests {
     async fn verify_chat_middleware_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
         let user = state.find_user_by_id(1).await?.expect("user should exist");
-        eprintln!("user: {:?}", user);
         let tonken = state.ek.sign(user)?;
-        eprintln!("token: {}", tonken);
         let app = Router::new()
-            .route("/chat/:id/message", get(handler))
+            .route("/chat/:id/message", get(handler).post(handler))
             .layer(from_fn_with_state(state.clone(), verify_chat))
             .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
-            .with_state(state);
+            .with_state(state.clone());
 
         // user in chat
         let req = Request::builder()
@@ -62,13 +76,40 @@ mod tests {
         let res = app.clone().oneshot(req).await?;
         assert_eq!(res.status(), StatusCode::OK);
 
-        // user not in chat
+        // chat in another workspace
         let req = Request::builder()
             .uri("/chat/5/message")
             .header("Authorization", format!("Bearer {}", tonken))
             .body(Body::empty())?;
 
         let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        // user not in chat
+        let user = state.find_user_by_id(4).await?.expect("user should exist");
+        let token = state.ek.sign(user)?;
+        let req = Request::builder()
+            .uri("/chat/2/message")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        // public channel the user isn't in: read-only
+        state.remove_chat_member(1, 4, 4, Role::Member, 1).await?;
+        let req = Request::builder()
+            .uri("/chat/1/message")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("/chat/1/message")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
         assert_eq!(res.status(), StatusCode::BAD_REQUEST);
         Ok(())
     }
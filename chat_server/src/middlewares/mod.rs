@@ -1,2 +1,4 @@
 mod chat;
+mod permission;
 pub use chat::verify_chat;
+pub use permission::require_permission;
@@ -0,0 +1,77 @@
+use axum::{
+    extract::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chat_core::{Permission, User};
+
+use crate::error::AppError;
+
+/// let the request through if the role in the caller's token grants `permission`, goes
+/// after `verify_token`, which refuses tokens whose role isn't the member's current one.
+/// checks depending on the request body or the resource are left to the models, they get
+/// the same role.
+///
+/// `.layer(from_fn(|req, next| require_permission(Permission::ManageInvites, req, next)))`
+pub async fn require_permission(permission: Permission, req: Request, next: Next) -> Response {
+    let Some(user) = req.extensions().get::<User>() else {
+        return AppError::Unauthorized("no user in request".to_string()).into_response();
+    };
+    if !user.role.can(permission) {
+        let err = AppError::PermissionDenied(format!("{:?} may not {:?}", user.role, permission));
+        return err.into_response();
+    }
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::{
+        body::Body, http::StatusCode, middleware::from_fn, middleware::from_fn_with_state, routing::get, Router,
+    };
+    use chat_core::{middlewares::verify_token, Role};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::AppState;
+
+    async fn handler(_req: Request) -> impl IntoResponse {
+        (StatusCode::OK, "ok")
+    }
+
+    #[tokio::test]
+    async fn require_permission_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let app = Router::new()
+            .route("/invites", get(handler))
+            .layer(from_fn(|req, next| {
+                require_permission(Permission::ManageInvites, req, next)
+            }))
+            .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
+            .with_state(state.clone());
+
+        let mut user = state.find_user_by_id(1).await?.expect("user should exist");
+        assert_eq!(user.role, Role::Owner);
+        for (role, status) in [
+            (Role::Owner, StatusCode::OK),
+            (Role::Admin, StatusCode::OK),
+            (Role::Member, StatusCode::FORBIDDEN),
+            (Role::Guest, StatusCode::FORBIDDEN),
+        ] {
+            // tokens only hold while their role is the member's current one
+            sqlx::query("UPDATE workspace_members SET role = $1 WHERE user_id = 1 AND ws_id = 1")
+                .bind(role)
+                .execute(&state.pool)
+                .await?;
+            user.role = role;
+            let req = Request::builder()
+                .uri("/invites")
+                .header("Authorization", format!("Bearer {}", state.ek.sign(user.clone())?))
+                .body(Body::empty())?;
+            let res = app.clone().oneshot(req).await?;
+            assert_eq!(res.status(), status, "{role:?}");
+        }
+        Ok(())
+    }
+}
@@ -1,49 +1,68 @@
-use chat_core::{Chat, ChatType};
+use std::collections::HashMap;
+
+use chat_core::{Chat, ChatType, Message, Permission, Role};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
 use utoipa::ToSchema;
 
 use crate::{error::AppError, AppState};
 
+const MAX_TOPIC_LEN: usize = 250;
+
 #[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
 pub struct CreateChat {
     pub name: Option<String>,
     pub members: Vec<i64>,
     pub public: bool,
+    #[serde(default)]
+    pub topic: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct UpdateChat {
+    pub name: Option<String>,
+    pub members: Option<Vec<i64>>,
+    pub public: Option<bool>,
+    /// an empty topic removes it
+    #[serde(default)]
+    pub topic: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct AddChatMembers {
+    pub members: Vec<i64>,
+}
+
+/// a public channel as listed for discovery
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct PublicChannel {
+    pub id: i64,
+    pub name: String,
+    pub topic: Option<String>,
+    pub member_count: i64,
+    /// whether the caller is a member
+    pub joined: bool,
+    pub created_at: DateTime<Utc>,
 }
+
 #[allow(unused)]
 impl AppState {
-    pub async fn create_chat(&self, input: CreateChat, ws_id: u64) -> Result<Chat, AppError> {
-        let len = input.members.len();
-        if len < 2 {
-            return Err(AppError::CreateChatError(
-                "Chat must have at least 2 members ".to_string(),
-            ));
+    /// `role` is the caller's, public channels need `Permission::CreatePublicChannel`
+    pub async fn create_chat(&self, input: CreateChat, role: Role, ws_id: u64) -> Result<Chat, AppError> {
+        if input.public && !role.can(Permission::CreatePublicChannel) {
+            return Err(AppError::PermissionDenied(format!(
+                "{role:?} may not create public channels"
+            )));
         }
-        if len > 8 && input.name.is_none() {
-            return Err(AppError::CreateChatError(
-                "Group chat with more than 8 members must have a name".to_string(),
-            ));
-        }
-        // verify if all members exists
-        let users = self.fetch_chat_user_by_ids(&input.members).await?;
-        if users.len() != len {
-            return Err(AppError::CreateChatError("Some members do not exist".to_string()));
-        }
-        let chat_type = match (&input.name, len) {
-            (None, 2) => ChatType::Single,
-            (None, _) => ChatType::Group,
-            (Some(_), _) => {
-                if input.public {
-                    ChatType::PublicChannel
-                } else {
-                    ChatType::PrivateChannel
-                }
-            }
-        };
+        self.verify_chat_members(ws_id, input.name.as_deref(), &input.members, AppError::CreateChatError)
+            .await?;
+        let topic = verify_topic(input.topic, AppError::CreateChatError)?;
+        let chat_type = get_chat_type(input.name.as_deref(), input.members.len(), input.public);
         let chat = sqlx::query_as(
             r#"
-            Insert into chats (ws_id, name, type, members)
-            values ($1, $2, $3, $4)
+            Insert into chats (ws_id, name, type, members, topic)
+            values ($1, $2, $3, $4, $5)
             returning *
             "#,
         )
@@ -51,49 +70,404 @@ impl AppState {
         .bind(input.name)
         .bind(chat_type)
         .bind(input.members)
+        .bind(topic)
         .fetch_one(&self.pool)
         .await?;
 
         Ok(chat)
     }
 
-    pub async fn fetch_chats(&self, ws_id: u64) -> Result<Vec<Chat>, AppError> {
-        let chats = sqlx::query_as(
+    /// `user_id` and `role` are the caller's, dropping others from the member list needs
+    /// `Permission::RemoveChatMembers`, leaving doesn't. making it public needs
+    /// `Permission::CreatePublicChannel`.
+    pub async fn update_chat_by_id(
+        &self,
+        id: u64,
+        input: UpdateChat,
+        user_id: u64,
+        role: Role,
+        ws_id: u64,
+    ) -> Result<Chat, AppError> {
+        if input.public == Some(true) && !role.can(Permission::CreatePublicChannel) {
+            return Err(AppError::PermissionDenied(format!(
+                "{role:?} may not create public channels"
+            )));
+        }
+        let mut tx = self.pool.begin().await?;
+        // lock the row so the members checked are the ones replaced
+        let chat: Option<Chat> = sqlx::query_as("select * from chats where id = $1 and ws_id = $2 for update")
+            .bind(id as i64)
+            .bind(ws_id as i64)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some(chat) = chat else {
+            return Err(AppError::NotFound(format!("chat id {id} not found")));
+        };
+        if let Some(members) = &input.members {
+            let removed = chat
+                .members
+                .iter()
+                .any(|v| *v != user_id as i64 && !members.contains(v));
+            if removed && !role.can(Permission::RemoveChatMembers) {
+                return Err(AppError::PermissionDenied(format!(
+                    "{role:?} may not remove others from a chat"
+                )));
+            }
+        }
+        let name = input.name.or(chat.name);
+        let members = input.members.unwrap_or(chat.members);
+        let public = input
+            .public
+            .unwrap_or(chat.r#type == ChatType::PublicChannel);
+        self.verify_chat_members(chat.ws_id as _, name.as_deref(), &members, AppError::UpdateChatError)
+            .await?;
+        let topic = match input.topic {
+            Some(topic) => verify_topic(Some(topic), AppError::UpdateChatError)?,
+            None => chat.topic,
+        };
+        let chat_type = get_chat_type(name.as_deref(), members.len(), public);
+        let chat = sqlx::query_as(
+            r#"
+            update chats set name = $1, type = $2, members = $3, topic = $4
+            where id = $5
+            returning *
+            "#,
+        )
+        .bind(name)
+        .bind(chat_type)
+        .bind(members)
+        .bind(topic)
+        .bind(id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(chat)
+    }
+
+    pub async fn add_chat_members(&self, id: u64, input: AddChatMembers, ws_id: u64) -> Result<Chat, AppError> {
+        let mut tx = self.pool.begin().await?;
+        // lock the row so concurrent membership changes can't overwrite each other
+        let chat: Option<Chat> = sqlx::query_as("select * from chats where id = $1 and ws_id = $2 for update")
+            .bind(id as i64)
+            .bind(ws_id as i64)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some(chat) = chat else {
+            return Err(AppError::NotFound(format!("chat id {id} not found")));
+        };
+
+        let mut added = input.members;
+        added.sort_unstable();
+        added.dedup();
+        added.retain(|v| !chat.members.contains(v));
+        if added.is_empty() {
+            return Ok(chat);
+        }
+        let users = self.fetch_chat_user_by_ids(&added, chat.ws_id as _).await?;
+        if users.len() != added.len() {
+            return Err(AppError::UpdateChatError("Some members do not exist".to_string()));
+        }
+
+        let len = chat.members.len() + added.len();
+        if len > 8 && chat.name.is_none() {
+            return Err(AppError::UpdateChatError(
+                "Group chat with more than 8 members must have a name".to_string(),
+            ));
+        }
+        let chat_type = match chat.r#type {
+            ChatType::Single => ChatType::Group,
+            t => t,
+        };
+        let chat = sqlx::query_as(
+            r#"
+            update chats set members = members || $1, type = $2
+            where id = $3
+            returning *
+            "#,
+        )
+        .bind(&added)
+        .bind(chat_type)
+        .bind(id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(chat)
+    }
+
+    /// `caller_id` and `role` are the caller's, anyone may leave but removing others needs
+    /// `Permission::RemoveChatMembers`
+    pub async fn remove_chat_member(
+        &self,
+        id: u64,
+        user_id: u64,
+        caller_id: u64,
+        role: Role,
+        ws_id: u64,
+    ) -> Result<Chat, AppError> {
+        if user_id != caller_id && !role.can(Permission::RemoveChatMembers) {
+            return Err(AppError::PermissionDenied(format!(
+                "{role:?} may not remove others from a chat"
+            )));
+        }
+        let mut tx = self.pool.begin().await?;
+        let chat: Option<Chat> = sqlx::query_as("select * from chats where id = $1 and ws_id = $2 for update")
+            .bind(id as i64)
+            .bind(ws_id as i64)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some(chat) = chat else {
+            return Err(AppError::NotFound(format!("chat id {id} not found")));
+        };
+        if !chat.members.contains(&(user_id as i64)) {
+            return Err(AppError::NotFound(format!(
+                "user {user_id} is not a member of chat {id}"
+            )));
+        }
+        // anyone may join a public channel, it may shrink to nobody
+        if chat.r#type != ChatType::PublicChannel && chat.members.len() <= 2 {
+            return Err(AppError::UpdateChatError(
+                "Chat must have at least 2 members ".to_string(),
+            ));
+        }
+
+        let chat = sqlx::query_as(
+            r#"
+            update chats set members = array_remove(members, $1)
+            where id = $2
+            returning *
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(chat)
+    }
+
+    /// add the user to a public channel of the workspace, a no-op if they are in it already
+    pub async fn join_chat(&self, id: u64, user_id: u64, ws_id: u64) -> Result<Chat, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let chat: Option<Chat> = sqlx::query_as("select * from chats where id = $1 and ws_id = $2 for update")
+            .bind(id as i64)
+            .bind(ws_id as i64)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some(chat) = chat else {
+            return Err(AppError::NotFound(format!("chat id {id} not found")));
+        };
+        if chat.r#type != ChatType::PublicChannel {
+            return Err(AppError::PermissionDenied(format!(
+                "chat {id} isn't a public channel, a member has to add you"
+            )));
+        }
+        if chat.members.contains(&(user_id as i64)) {
+            return Ok(chat);
+        }
+        let chat = sqlx::query_as(
+            r#"
+            update chats set members = array_append(members, $1)
+            where id = $2
+            returning *
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(chat)
+    }
+
+    /// public channels of the workspace, whether or not the user is in them
+    pub async fn fetch_public_channels(&self, ws_id: u64, user_id: u64) -> Result<Vec<PublicChannel>, AppError> {
+        let channels = sqlx::query_as(
             r#"
-            select * from chats where ws_id = $1
-        "#,
+            select id, name, topic, cardinality(members)::bigint as member_count,
+                $2 = any(members) as joined, created_at
+            from chats
+            where ws_id = $1 and type = 'public_channel'
+            order by name, id
+            "#,
         )
         .bind(ws_id as i64)
+        .bind(user_id as i64)
         .fetch_all(&self.pool)
         .await?;
+        Ok(channels)
+    }
+
+    /// messages of the chat are removed along with it (`ON DELETE CASCADE`). either side may
+    /// delete a single chat, groups and channels need `Permission::DeleteChat` of the caller's `role`
+    pub async fn delete_chat_by_id(&self, id: u64, role: Role, ws_id: u64) -> Result<Chat, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let chat: Option<Chat> = sqlx::query_as("select * from chats where id = $1 and ws_id = $2 for update")
+            .bind(id as i64)
+            .bind(ws_id as i64)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some(chat) = chat else {
+            return Err(AppError::NotFound(format!("chat id {id} not found")));
+        };
+        if chat.r#type != ChatType::Single && !role.can(Permission::DeleteChat) {
+            return Err(AppError::PermissionDenied(format!(
+                "{role:?} may not delete a {:?} chat",
+                chat.r#type
+            )));
+        }
+        sqlx::query("delete from chats where id = $1")
+            .bind(id as i64)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(chat)
+    }
+
+    /// chats of the workspace with the caller's unread count and the latest message of the
+    /// ones they're in. replies and the caller's own messages don't count as unread.
+    pub async fn fetch_chats(&self, ws_id: u64, user_id: u64) -> Result<Vec<Chat>, AppError> {
+        let mut chats: Vec<Chat> = sqlx::query_as(
+            r#"
+            select c.*, case when $2 = any(c.members) then (
+                select count(*) from messages m
+                where m.chat_id = c.id and m.parent_id is null and m.deleted_at is null
+                and m.sender_id <> $2 and m.id > coalesce(r.last_read_message_id, 0)
+            ) else 0 end as unread
+            from chats c left join chat_reads r on r.chat_id = c.id and r.user_id = $2
+            where c.ws_id = $1
+            order by c.id
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        // only members may read a chat, the others just see it listed
+        let ids: Vec<i64> = chats
+            .iter()
+            .filter(|c| c.members.contains(&(user_id as i64)))
+            .map(|c| c.id)
+            .collect();
+        let messages: Vec<Message> = sqlx::query_as(
+            r#"
+            select distinct on (chat_id) * from messages
+            where chat_id = any($1) and parent_id is null and deleted_at is null
+            order by chat_id, id desc
+            "#,
+        )
+        .bind(&ids)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut messages: HashMap<i64, Message> = messages.into_iter().map(|m| (m.chat_id, m)).collect();
+        for chat in chats.iter_mut() {
+            chat.last_message = messages.remove(&chat.id);
+        }
+        self.attach_files(
+            chats
+                .iter_mut()
+                .filter_map(|c| c.last_message.as_mut())
+                .collect(),
+        )
+        .await?;
 
         Ok(chats)
     }
 
-    pub async fn get_chat_by_id(&self, id: u64) -> Result<Option<Chat>, AppError> {
+    pub async fn get_chat_by_id(&self, id: u64, ws_id: u64) -> Result<Option<Chat>, AppError> {
         let chat = sqlx::query_as(
             r#"
-            select * from chats where id = $1
+            select * from chats where id = $1 and ws_id = $2
             "#,
         )
         .bind(id as i64)
+        .bind(ws_id as i64)
         .fetch_optional(&self.pool)
         .await?;
         Ok(chat)
     }
 
-    pub async fn is_chat_member(&self, chat_id: u64, user_id: u64) -> Result<bool, AppError> {
-        let is_member: Option<Chat> = sqlx::query_as(
+    pub async fn is_chat_member(&self, chat_id: u64, user_id: u64, ws_id: u64) -> Result<bool, AppError> {
+        let ids = self.member_chat_ids(user_id, ws_id, Some(chat_id)).await?;
+        Ok(!ids.is_empty())
+    }
+
+    /// ids of the chats of the workspace the user is a member of, or just `chat_id` if given and
+    /// the user is in it. everything scoped to "chats I'm in" should go through here.
+    pub(crate) async fn member_chat_ids(
+        &self,
+        user_id: u64,
+        ws_id: u64,
+        chat_id: Option<u64>,
+    ) -> Result<Vec<i64>, AppError> {
+        let ids = sqlx::query_scalar(
             r#"
-                select *
-                from chats where id =$1 and $2 = any(members)
+                select id
+                from chats where $1 = any(members) and ws_id = $2 and ($3::bigint is null or id = $3)
             "#,
         )
-        .bind(chat_id as i64)
         .bind(user_id as i64)
-        .fetch_optional(&self.pool)
+        .bind(ws_id as i64)
+        .bind(chat_id.map(|v| v as i64))
+        .fetch_all(&self.pool)
         .await?;
-        Ok(is_member.is_some())
+        Ok(ids)
+    }
+
+    /// shared by create/update so that both apply the same member rules
+    async fn verify_chat_members(
+        &self,
+        ws_id: u64,
+        name: Option<&str>,
+        members: &[i64],
+        err: fn(String) -> AppError,
+    ) -> Result<(), AppError> {
+        let len = members.len();
+        if len < 2 {
+            return Err(err("Chat must have at least 2 members ".to_string()));
+        }
+        if len > 8 && name.is_none() {
+            return Err(err("Group chat with more than 8 members must have a name".to_string()));
+        }
+        // verify if all members exists in the workspace
+        let users = self.fetch_chat_user_by_ids(members, ws_id).await?;
+        if users.len() != len {
+            return Err(err("Some members do not exist".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// the topic trimmed, `None` if it's empty
+fn verify_topic(topic: Option<String>, err: fn(String) -> AppError) -> Result<Option<String>, AppError> {
+    let Some(topic) = topic
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+    else {
+        return Ok(None);
+    };
+    if topic.chars().count() > MAX_TOPIC_LEN {
+        return Err(err(format!("topic must be at most {MAX_TOPIC_LEN} characters")));
+    }
+    Ok(Some(topic))
+}
+
+fn get_chat_type(name: Option<&str>, len: usize, public: bool) -> ChatType {
+    match (name, len) {
+        (None, 2) => ChatType::Single,
+        (None, _) => ChatType::Group,
+        (Some(_), _) => {
+            if public {
+                ChatType::PublicChannel
+            } else {
+                ChatType::PrivateChannel
+            }
+        }
     }
 }
 #[cfg(test)]
@@ -104,9 +478,28 @@ impl CreateChat {
             name,
             members: members.to_vec(),
             public,
+            topic: None,
         }
     }
 }
+#[cfg(test)]
+impl UpdateChat {
+    pub fn new(name: &str, members: &[i64], public: Option<bool>) -> Self {
+        let name = if name.is_empty() { None } else { Some(name.to_string()) };
+        let members = if members.is_empty() {
+            None
+        } else {
+            Some(members.to_vec())
+        };
+        Self {
+            name,
+            members,
+            public,
+            topic: None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -118,7 +511,7 @@ mod tests {
         let (_tdb, state) = AppState::new_for_test().await?;
         let input = CreateChat::new("", &[1, 2], false);
         let chat = state
-            .create_chat(input, 1)
+            .create_chat(input, Role::Owner, 1)
             .await
             .expect("create chat failed");
         assert_eq!(chat.ws_id, 1);
@@ -131,7 +524,7 @@ mod tests {
         let (_tdb, state) = AppState::new_for_test().await?;
         let input = CreateChat::new("general", &[1, 2, 3], true);
         let chat = state
-            .create_chat(input, 1)
+            .create_chat(input, Role::Owner, 1)
             .await
             .expect("create chat failed");
         assert_eq!(chat.name, Some("general".to_string()));
@@ -145,7 +538,7 @@ mod tests {
         let (_tdb, state) = AppState::new_for_test().await?;
 
         let chat = state
-            .get_chat_by_id(1)
+            .get_chat_by_id(1, 1)
             .await
             .expect("get chat failed")
             .unwrap();
@@ -158,8 +551,216 @@ mod tests {
     #[tokio::test]
     async fn chat_fetch_all_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
-        let chats = state.fetch_chats(1).await.expect("fetch all chats failed");
+        let chats = state
+            .fetch_chats(1, 1)
+            .await
+            .expect("fetch all chats failed");
         assert_eq!(chats.len(), 4);
+        // user 1 sent 4 of the 10 messages in chat 1, the other chats are empty
+        assert_eq!(chats[0].unread, 6);
+        assert_eq!(chats[0].last_message.as_ref().map(|m| m.id), Some(10));
+        assert!(chats[1..]
+            .iter()
+            .all(|c| c.unread == 0 && c.last_message.is_none()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_fetch_all_should_hide_messages_of_other_chats() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // chat 2 is the private channel of users 1, 2 and 3
+        sqlx::query("INSERT INTO messages (chat_id, sender_id, content) VALUES (2, 1, 'secret')")
+            .execute(&state.pool)
+            .await?;
+        let chats = state.fetch_chats(1, 1).await?;
+        assert_eq!(
+            chats[1].last_message.as_ref().map(|m| m.content.as_str()),
+            Some("secret")
+        );
+
+        let chats = state.fetch_chats(1, 4).await?;
+        assert_eq!(chats[1].id, 2);
+        assert!(chats[1].last_message.is_none());
+        assert_eq!(chats[1].unread, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_chat_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // single chat grows into a group
+        let input = UpdateChat::new("", &[1, 2, 3], None);
+        let chat = state.update_chat_by_id(3, input, 1, Role::Owner, 1).await?;
+        assert_eq!(chat.members, vec![1, 2, 3]);
+        assert_eq!(chat.r#type, ChatType::Group);
+
+        // naming a group turns it into a channel
+        let input = UpdateChat::new("team", &[], Some(true));
+        let chat = state.update_chat_by_id(3, input, 1, Role::Owner, 1).await?;
+        assert_eq!(chat.name, Some("team".to_string()));
+        assert_eq!(chat.r#type, ChatType::PublicChannel);
+
+        // keep public flag when it's not given
+        let input = UpdateChat::new("team2", &[], None);
+        let chat = state.update_chat_by_id(3, input, 1, Role::Owner, 1).await?;
+        assert_eq!(chat.r#type, ChatType::PublicChannel);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_chat_with_invalid_members_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = UpdateChat::new("", &[1], None);
+        let err = state
+            .update_chat_by_id(3, input, 1, Role::Owner, 1)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "update chat error: Chat must have at least 2 members ");
+
+        let input = UpdateChat::new("", &[1, 100], None);
+        let err = state
+            .update_chat_by_id(3, input, 1, Role::Owner, 1)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "update chat error: Some members do not exist");
+
+        let input = UpdateChat::new("", &[1, 2], None);
+        let err = state
+            .update_chat_by_id(10, input, 1, Role::Owner, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn add_chat_members_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = AddChatMembers { members: vec![2, 3, 3] };
+        let chat = state.add_chat_members(3, input, 1).await?;
+        assert_eq!(chat.members, vec![1, 2, 3]);
+        assert_eq!(chat.r#type, ChatType::Group);
+
+        let input = AddChatMembers { members: vec![100] };
+        let err = state.add_chat_members(3, input, 1).await.unwrap_err();
+        assert_eq!(err.to_string(), "update chat error: Some members do not exist");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn remove_chat_member_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let chat = state.remove_chat_member(4, 3, 1, Role::Owner, 1).await?;
+        assert_eq!(chat.members, vec![1, 4]);
+        assert_eq!(chat.r#type, ChatType::Group);
+
+        // not a member anymore
+        let err = state
+            .remove_chat_member(4, 3, 1, Role::Owner, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        // chat needs at least 2 members
+        let err = state
+            .remove_chat_member(4, 4, 1, Role::Owner, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::UpdateChatError(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn public_channels_should_be_joined_and_left() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut input = UpdateChat::new("", &[], None);
+        input.topic = Some(" all things acme ".to_string());
+        state.update_chat_by_id(1, input, 1, Role::Owner, 1).await?;
+        state.remove_chat_member(1, 5, 1, Role::Owner, 1).await?;
+
+        let channels = state.fetch_public_channels(1, 5).await?;
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].name, "general");
+        assert_eq!(channels[0].topic.as_deref(), Some("all things acme"));
+        assert_eq!(channels[0].member_count, 4);
+        assert!(!channels[0].joined);
+        assert!(state.fetch_public_channels(2, 6).await?.is_empty());
+
+        let chat = state.join_chat(1, 5, 1).await?;
+        assert_eq!(chat.members, vec![1, 2, 3, 4, 5]);
+        // joining twice is fine
+        assert_eq!(state.join_chat(1, 5, 1).await?, chat);
+        // private channels and other workspaces' chats can't be joined
+        let err = state.join_chat(2, 5, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+        let err = state.join_chat(1, 6, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        let mut input = UpdateChat::new("", &[], None);
+        input.topic = Some("a".repeat(MAX_TOPIC_LEN + 1));
+        let err = state
+            .update_chat_by_id(1, input, 1, Role::Owner, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::UpdateChatError(_)));
+
+        // everyone may leave a public channel
+        for user_id in 1..=5 {
+            state
+                .remove_chat_member(1, user_id, user_id, Role::Member, 1)
+                .await?;
+        }
+        assert_eq!(state.fetch_public_channels(1, 5).await?[0].member_count, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_chat_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // chat 1 has messages, they should be deleted as well
+        let chat = state.delete_chat_by_id(1, Role::Owner, 1).await?;
+        assert_eq!(chat.id, 1);
+        assert!(state.get_chat_by_id(1, 1).await?.is_none());
+
+        let err = state
+            .delete_chat_by_id(1, Role::Owner, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_changes_should_need_a_role() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // user 2 is a member, chat 2 the private channel of users 1, 2 and 3
+        let input = UpdateChat::new("", &[1, 2], None);
+        let err = state
+            .update_chat_by_id(2, input, 2, Role::Member, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+        let err = state
+            .delete_chat_by_id(2, Role::Member, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+        let err = state
+            .remove_chat_member(2, 3, 2, Role::Member, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+        let input = CreateChat::new("guests", &[1, 2, 3], true);
+        let err = state.create_chat(input, Role::Guest, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        // leaving is fine, so is deleting a single chat
+        let input = UpdateChat::new("", &[1, 3], None);
+        let chat = state
+            .update_chat_by_id(2, input, 2, Role::Member, 1)
+            .await?;
+        assert_eq!(chat.members, vec![1, 3]);
+        state.delete_chat_by_id(3, Role::Member, 1).await?;
         Ok(())
     }
 
@@ -167,23 +768,79 @@ mod tests {
     async fn is_chat_member_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
         let is_member = state
-            .is_chat_member(1, 1)
+            .is_chat_member(1, 1, 1)
             .await
             .expect("is chat member failed");
         assert!(is_member);
 
-        // user 6 doesn't exist
-        let is_member = state.is_chat_member(1, 6).await.expect("is member failed");
+        // user 6 belongs to another workspace
+        let is_member = state
+            .is_chat_member(1, 6, 1)
+            .await
+            .expect("is member failed");
         assert!(!is_member);
 
         // chat 10 doesn't exist
-        let is_member = state.is_chat_member(10, 1).await.expect("is member failed");
+        let is_member = state
+            .is_chat_member(10, 1, 1)
+            .await
+            .expect("is member failed");
         assert!(!is_member);
 
         // user 4 is not a member of chat 2
-        let is_member = state.is_chat_member(2, 4).await.expect("is member failed");
+        let is_member = state
+            .is_chat_member(2, 4, 1)
+            .await
+            .expect("is member failed");
+        assert!(!is_member);
+
+        // chat 5 belongs to workspace 2
+        let is_member = state
+            .is_chat_member(5, 6, 1)
+            .await
+            .expect("is member failed");
         assert!(!is_member);
+        let is_member = state
+            .is_chat_member(5, 6, 2)
+            .await
+            .expect("is member failed");
+        assert!(is_member);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cross_workspace_chat_access_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        assert!(state.get_chat_by_id(5, 1).await?.is_none());
+
+        let input = UpdateChat::new("hacked", &[], None);
+        let err = state
+            .update_chat_by_id(5, input, 1, Role::Owner, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        let input = AddChatMembers { members: vec![1] };
+        let err = state.add_chat_members(5, input, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        let err = state
+            .remove_chat_member(5, 6, 1, Role::Owner, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        let err = state
+            .delete_chat_by_id(5, Role::Owner, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
 
+        // members from another workspace are rejected
+        let input = CreateChat::new("", &[1, 6], false);
+        let err = state.create_chat(input, Role::Owner, 1).await.unwrap_err();
+        assert_eq!(err.to_string(), "create chat error: Some members do not exist");
         Ok(())
     }
 }
@@ -1,35 +1,297 @@
-use std::{
-    path::{Path, PathBuf},
-    str::FromStr,
-};
+use std::{collections::HashMap, str::FromStr};
 
+use chat_core::Message;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use sha1::{Digest, Sha1};
+use sqlx::FromRow;
+use tracing::{info, warn};
+use utoipa::ToSchema;
 
 use super::ChatFile;
-use crate::error::AppError;
+use crate::{
+    error::AppError,
+    storage::{FileContent, FileStore},
+    AppState,
+};
+
+/// an uploaded file as recorded in the files table
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct FileInfo {
+    pub id: i64,
+    pub ws_id: i64,
+    pub uploader_id: Option<i64>,
+    pub name: String,
+    pub size: i64,
+    pub mime: String,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    #[serde(skip)]
+    pub hash: String,
+    #[serde(skip)]
+    pub ext: String,
+    #[sqlx(skip)]
+    pub url: String,
+    pub created_at: DateTime<Utc>,
+}
 
 impl ChatFile {
     pub fn new(ws_id: u64, filename: &str, data: &[u8]) -> Self {
-        let hash = Sha1::digest(data);
+        Self::from_hash(ws_id, filename, hex::encode(Sha1::digest(data)))
+    }
+
+    /// for content which was hashed while it was received
+    pub fn from_hash(ws_id: u64, filename: &str, hash: String) -> Self {
         Self {
             ws_id,
-            ext: filename.split('.').last().unwrap_or("txt").to_string(),
-            hash: hex::encode(hash),
+            ext: filename.rsplit('.').next().unwrap_or("txt").to_string(),
+            hash,
         }
     }
 
     pub fn url(&self) -> String {
-        format!("/files/{}", self.hash_to_path())
+        format!("/files/{}", self.key())
     }
 
-    pub fn path(&self, base_dir: &Path) -> PathBuf {
-        base_dir.join(self.hash_to_path())
+    /// where the file is kept in the `FileStore`
+    pub fn key(&self) -> String {
+        let (part1, part2) = self.hash.split_at(3);
+        let (part2, part3) = part2.split_at(3);
+        format!("{}/{}/{}/{}.{}", self.ws_id, part1, part2, part3, self.ext)
     }
 
-    fn hash_to_path(&self) -> String {
+    /// where the thumbnail of an image with `size` pixels on its longest side is kept
+    pub fn thumbnail_key(&self, size: u32) -> String {
         let (part1, part2) = self.hash.split_at(3);
         let (part2, part3) = part2.split_at(3);
-        format!("{}/{}/{}/{}.{}", self.ws_id, part1, part2, part3, self.ext)
+        format!("{}/{}/{}/{}_{}.webp", self.ws_id, part1, part2, part3, size)
+    }
+}
+
+impl AppState {
+    /// record an upload and store its content, if the workspace has the same content already that
+    /// file is returned. `dimensions` are the pixels of an image.
+    /// new content is counted against the workspace's storage quota, `AppError::PayloadTooLarge`
+    /// if it doesn't fit. the row and the charge are only committed once the content is stored.
+    pub async fn create_file<'a>(
+        &self,
+        file: &ChatFile,
+        content: impl Into<FileContent<'a>>,
+        name: &str,
+        mime: &str,
+        dimensions: Option<(u32, u32)>,
+        uploader_id: u64,
+    ) -> Result<FileInfo, AppError> {
+        let content = content.into();
+        let size = content.size().await?;
+        let mut tx = self.pool.begin().await?;
+        let info: Option<FileInfo> = sqlx::query_as(
+            r#"
+            INSERT INTO files (ws_id, uploader_id, hash, ext, name, size, mime, width, height)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            ON CONFLICT (ws_id, hash, ext) DO NOTHING
+            RETURNING *
+            "#,
+        )
+        .bind(file.ws_id as i64)
+        .bind(uploader_id as i64)
+        .bind(&file.hash)
+        .bind(&file.ext)
+        .bind(name)
+        .bind(size as i64)
+        .bind(mime)
+        .bind(dimensions.map(|(w, _)| w as i32))
+        .bind(dimensions.map(|(_, h)| h as i32))
+        .fetch_optional(&mut *tx)
+        .await?;
+        let info = match info {
+            Some(info) => {
+                let reserved = sqlx::query(
+                    r#"
+                    UPDATE workspaces
+                    SET storage_used = storage_used + $2
+                    WHERE id = $1 AND storage_used + $2 <= coalesce(storage_quota, $3)
+                    "#,
+                )
+                .bind(file.ws_id as i64)
+                .bind(size as i64)
+                .bind(self.config.server.upload.workspace_quota as i64)
+                .execute(&mut *tx)
+                .await?;
+                if reserved.rows_affected() == 0 {
+                    return Err(AppError::PayloadTooLarge(format!(
+                        "workspace storage quota exceeded by {name}"
+                    )));
+                }
+                info
+            }
+            // known content, it counts as fresh for garbage collection again
+            None => {
+                sqlx::query_as(
+                    r#"
+                    UPDATE files SET uploaded_at = now()
+                    WHERE ws_id = $1 AND hash = $2 AND ext = $3
+                    RETURNING *
+                    "#,
+                )
+                .bind(file.ws_id as i64)
+                .bind(&file.hash)
+                .bind(&file.ext)
+                .fetch_one(&mut *tx)
+                .await?
+            }
+        };
+
+        // the row is locked until the commit, garbage collection can't remove the content meanwhile
+        let key = file.key();
+        if self.store.head(&key).await?.is_some() {
+            info!("File {} already exists {}", name, key);
+        } else {
+            match content {
+                FileContent::Bytes(data) => self.store.put(&key, data).await?,
+                FileContent::Path(path) => self.store.put_file(&key, path).await?,
+            }
+        }
+        tx.commit().await?;
+        Ok(info.with_url())
+    }
+
+    pub async fn get_file_info(&self, id: u64, ws_id: u64) -> Result<FileInfo, AppError> {
+        let info: Option<FileInfo> = sqlx::query_as("SELECT * FROM files WHERE id = $1 AND ws_id = $2")
+            .bind(id as i64)
+            .bind(ws_id as i64)
+            .fetch_optional(&self.pool)
+            .await?;
+        info.map(FileInfo::with_url)
+            .ok_or_else(|| AppError::NotFound(format!("file id {id} not found")))
+    }
+
+    pub async fn find_file_info(&self, file: &ChatFile) -> Result<Option<FileInfo>, AppError> {
+        let info: Option<FileInfo> = sqlx::query_as("SELECT * FROM files WHERE ws_id = $1 AND hash = $2 AND ext = $3")
+            .bind(file.ws_id as i64)
+            .bind(&file.hash)
+            .bind(&file.ext)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(info.map(FileInfo::with_url))
+    }
+
+    /// record the files messages refer to which were uploaded before uploads were recorded, so
+    /// they can be sent again and are collected once no message refers to them anymore. the
+    /// content is counted against the quota. files never sent can't be found this way, they
+    /// are left in the store. returns the number of files recorded.
+    pub async fn record_sent_files(&self) -> Result<u64, AppError> {
+        let sent: Vec<(i64, String)> = sqlx::query_as(
+            r#"
+            SELECT DISTINCT c.ws_id, u.url
+            FROM messages m
+            JOIN chats c ON c.id = m.chat_id, unnest(m.files) AS u(url)
+            WHERE NOT EXISTS (SELECT 1 FROM files f WHERE f.ws_id = c.ws_id AND file_url(f) = u.url)
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut recorded = 0;
+        for (ws_id, url) in sent {
+            let Ok(file) = url.parse::<ChatFile>() else {
+                continue;
+            };
+            if file.ws_id as i64 != ws_id {
+                continue;
+            }
+            let Some(meta) = self.store.head(&file.key()).await? else {
+                warn!("File {} of a message is missing", url);
+                continue;
+            };
+            let mime = mime_guess::from_ext(&file.ext).first_or_octet_stream();
+            let mut tx = self.pool.begin().await?;
+            let ret = sqlx::query(
+                r#"
+                INSERT INTO files (ws_id, hash, ext, name, size, mime)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                ON CONFLICT (ws_id, hash, ext) DO NOTHING
+                "#,
+            )
+            .bind(ws_id)
+            .bind(&file.hash)
+            .bind(&file.ext)
+            .bind(format!("{}.{}", file.hash, file.ext))
+            .bind(meta.size as i64)
+            .bind(mime.as_ref())
+            .execute(&mut *tx)
+            .await?;
+            if ret.rows_affected() == 0 {
+                continue;
+            }
+            sqlx::query("UPDATE workspaces SET storage_used = storage_used + $2 WHERE id = $1")
+                .bind(ws_id)
+                .bind(meta.size as i64)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            recorded += 1;
+        }
+        Ok(recorded)
+    }
+
+    /// fill in name, size and mime of the message files which were recorded on upload
+    pub(crate) async fn attach_files(&self, mut messages: Vec<&mut Message>) -> Result<(), AppError> {
+        let files: Vec<ChatFile> = messages
+            .iter()
+            .flat_map(|m| &m.files)
+            .filter_map(|f| ChatFile::from_str(&f.url).ok())
+            .collect();
+        if files.is_empty() {
+            return Ok(());
+        }
+        let ws_ids: Vec<i64> = files.iter().map(|f| f.ws_id as i64).collect();
+        let hashes: Vec<&str> = files.iter().map(|f| f.hash.as_str()).collect();
+        let exts: Vec<&str> = files.iter().map(|f| f.ext.as_str()).collect();
+        let infos: Vec<FileInfo> = sqlx::query_as(
+            r#"
+            SELECT * FROM files
+            WHERE (ws_id, hash, ext) IN (SELECT * FROM unnest($1::bigint[], $2::text[], $3::text[]))
+            "#,
+        )
+        .bind(&ws_ids)
+        .bind(&hashes)
+        .bind(&exts)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let infos: HashMap<String, FileInfo> = infos
+            .into_iter()
+            .map(|info| {
+                let info = info.with_url();
+                (info.url.clone(), info)
+            })
+            .collect();
+        for file in messages.iter_mut().flat_map(|m| m.files.iter_mut()) {
+            if let Some(info) = infos.get(&file.url) {
+                file.name.clone_from(&info.name);
+                file.size = Some(info.size);
+                file.mime = Some(info.mime.clone());
+                file.width = info.width;
+                file.height = info.height;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FileInfo {
+    pub(crate) fn chat_file(&self) -> ChatFile {
+        ChatFile {
+            ws_id: self.ws_id as _,
+            ext: self.ext.clone(),
+            hash: self.hash.clone(),
+        }
+    }
+
+    pub(super) fn with_url(mut self) -> Self {
+        self.url = self.chat_file().url();
+        self
     }
 }
 
@@ -68,6 +330,8 @@ impl FromStr for ChatFile {
 
 #[cfg(test)]
 mod tests {
+    use bytes::Bytes;
+
     use super::*;
 
     #[test]
@@ -75,5 +339,195 @@ mod tests {
         let file = ChatFile::new(1, "test.txt", b"hello world");
         assert_eq!(file.ext, "txt");
         assert_eq!(file.hash, "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed");
+        assert_eq!(file.key(), "1/2aa/e6c/35c94fcfb415dbe95f408b9ce91ee846ed.txt");
+        assert_eq!(
+            file.thumbnail_key(256),
+            "1/2aa/e6c/35c94fcfb415dbe95f408b9ce91ee846ed_256.webp"
+        );
+    }
+
+    #[test]
+    fn chat_file_ext_should_be_the_last_suffix() {
+        let file = ChatFile::new(1, "backup.tar.gz", b"hello world");
+        assert_eq!(file.ext, "gz");
+        assert!(file.url().ends_with(".gz"));
+    }
+
+    #[tokio::test]
+    async fn file_info_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let file = ChatFile::new(1, "test.txt", b"hello world");
+        let info = state
+            .create_file(
+                &file,
+                Bytes::from_static(b"hello world"),
+                "test.txt",
+                "text/plain",
+                None,
+                1,
+            )
+            .await?;
+        assert_eq!(info.url, file.url());
+        assert_eq!(info.uploader_id, Some(1));
+
+        // the same content again is the same file
+        let again = state
+            .create_file(
+                &file,
+                Bytes::from_static(b"hello world"),
+                "copy.txt",
+                "text/plain",
+                None,
+                2,
+            )
+            .await?;
+        assert_eq!(again.id, info.id);
+        assert_eq!(again.name, "test.txt");
+
+        assert_eq!(state.get_file_info(info.id as _, 1).await?, info);
+        let err = state.get_file_info(info.id as _, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        assert_eq!(state.find_file_info(&file).await?, Some(info));
+
+        let image = ChatFile::new(1, "a.png", b"not really a png");
+        let info = state
+            .create_file(
+                &image,
+                Bytes::from_static(b"not really a png"),
+                "a.png",
+                "image/png",
+                Some((640, 480)),
+                1,
+            )
+            .await?;
+        assert_eq!((info.width, info.height), (Some(640), Some(480)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_file_should_respect_quota() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        sqlx::query("UPDATE workspaces SET storage_quota = 20 WHERE id = 1")
+            .execute(&state.pool)
+            .await?;
+        let file = ChatFile::new(1, "a.txt", b"hello world");
+        state
+            .create_file(
+                &file,
+                Bytes::from_static(b"hello world"),
+                "a.txt",
+                "text/plain",
+                None,
+                1,
+            )
+            .await?;
+        // known content takes no more space
+        state
+            .create_file(
+                &file,
+                Bytes::from_static(b"hello world"),
+                "a.txt",
+                "text/plain",
+                None,
+                1,
+            )
+            .await?;
+
+        let other = ChatFile::new(1, "b.txt", b"hello again");
+        let err = state
+            .create_file(
+                &other,
+                Bytes::from_static(b"hello again"),
+                "b.txt",
+                "text/plain",
+                None,
+                1,
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::PayloadTooLarge(_)));
+        assert_eq!(state.find_file_info(&other).await?, None);
+
+        // other workspaces use the configured default
+        let other = ChatFile::new(2, "b.txt", b"hello again");
+        state
+            .create_file(
+                &other,
+                Bytes::from_static(b"hello again"),
+                "b.txt",
+                "text/plain",
+                None,
+                6,
+            )
+            .await?;
+        let used: i64 = sqlx::query_scalar("SELECT storage_used FROM workspaces WHERE id = 1")
+            .fetch_one(&state.pool)
+            .await?;
+        assert_eq!(used, 11);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_file_should_not_keep_a_file_it_could_not_store() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // the store is shared with other tests, the content must be unique
+        let data = format!("unstorable {}", uuid::Uuid::now_v7());
+        let file = ChatFile::new(1, "a.txt", data.as_bytes());
+        // a directory where the content goes makes the store fail
+        let dir = state.config.server.base_dir.join(file.key());
+        tokio::fs::create_dir_all(&dir).await?;
+        let ret = state
+            .create_file(&file, Bytes::from(data.clone()), "a.txt", "text/plain", None, 1)
+            .await;
+        tokio::fs::remove_dir(&dir).await?;
+        assert!(ret.is_err());
+        assert_eq!(state.find_file_info(&file).await?, None);
+        let used: i64 = sqlx::query_scalar("SELECT storage_used FROM workspaces WHERE id = 1")
+            .fetch_one(&state.pool)
+            .await?;
+        assert_eq!(used, 0);
+
+        state
+            .create_file(&file, Bytes::from(data), "a.txt", "text/plain", None, 1)
+            .await?;
+        assert!(state.store.head(&file.key()).await?.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn record_sent_files_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // sent before uploads were recorded, the store is shared with other tests
+        let data = format!("sent long ago {}", uuid::Uuid::now_v7());
+        let file = ChatFile::new(1, "a.txt", data.as_bytes());
+        state
+            .store
+            .put(&file.key(), Bytes::from(data.clone()))
+            .await?;
+        let missing = ChatFile::new(1, "b.txt", uuid::Uuid::now_v7().as_bytes());
+        sqlx::query("INSERT INTO messages (chat_id, sender_id, content, files) VALUES (1, 1, 'old', $1)")
+            .bind(vec![file.url(), missing.url()])
+            .execute(&state.pool)
+            .await?;
+
+        assert_eq!(state.record_sent_files().await?, 1);
+        let info = state.find_file_info(&file).await?.unwrap();
+        assert_eq!(info.size, data.len() as i64);
+        assert_eq!(info.mime, "text/plain");
+        assert_eq!(state.find_file_info(&missing).await?, None);
+        let used: i64 = sqlx::query_scalar("SELECT storage_used FROM workspaces WHERE id = 1")
+            .fetch_one(&state.pool)
+            .await?;
+        assert_eq!(used, info.size);
+        assert_eq!(state.record_sent_files().await?, 0);
+
+        // it can be sent again
+        let input = crate::CreateMessage {
+            content: "again".to_string(),
+            files: vec![file.url()],
+            parent_id: None,
+        };
+        state.create_message(input, 1, 1, 1).await?;
+        Ok(())
     }
 }
@@ -0,0 +1,178 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use super::FileInfo;
+use crate::{error::AppError, storage::FileStore, AppState};
+
+// files no message refers to which weren't uploaded within the grace period ($2 seconds),
+// of workspace $1 or of all of them
+const UNUSED_FILES: &str = r#"
+    ($1::bigint IS NULL OR f.ws_id = $1)
+    AND f.uploaded_at <= now() - make_interval(secs => $2)
+    AND NOT EXISTS (SELECT 1 FROM messages m WHERE m.files @> ARRAY[file_url(f)])
+"#;
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct CollectFiles {
+    /// only report what would be removed
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct FileGcReport {
+    pub dry_run: bool,
+    /// files removed, or which would be on a dry run
+    pub files: Vec<FileInfo>,
+    /// bytes reclaimed, or which would be on a dry run
+    pub bytes: i64,
+}
+
+impl AppState {
+    /// remove the files of a workspace, or of all of them, which no message refers to and
+    /// which weren't uploaded within `grace_period`. the content and thumbnails go too, the
+    /// space is given back to the workspace's quota. only recorded files are known, see
+    /// `record_sent_files` for the ones uploaded before that.
+    pub async fn collect_files(
+        &self,
+        ws_id: Option<u64>,
+        grace_period: Duration,
+        dry_run: bool,
+    ) -> Result<FileGcReport, AppError> {
+        let ws_id = ws_id.map(|v| v as i64);
+        let grace_period = grace_period.as_secs_f64();
+        let candidates: Vec<FileInfo> =
+            sqlx::query_as(&format!("SELECT * FROM files f WHERE {UNUSED_FILES} ORDER BY f.id"))
+                .bind(ws_id)
+                .bind(grace_period)
+                .fetch_all(&self.pool)
+                .await?;
+        if dry_run {
+            let bytes = candidates.iter().map(|f| f.size).sum();
+            let files = candidates.into_iter().map(FileInfo::with_url).collect();
+            return Ok(FileGcReport { dry_run, files, bytes });
+        }
+
+        let mut files = vec![];
+        for candidate in candidates {
+            // it may have been sent or uploaded again meanwhile. the lock waits for a message
+            // being sent with it, the delete then sees that message.
+            let mut tx = self.pool.begin().await?;
+            sqlx::query("SELECT 1 FROM files WHERE id = $1 FOR UPDATE")
+                .bind(candidate.id)
+                .execute(&mut *tx)
+                .await?;
+            let info: Option<FileInfo> = sqlx::query_as(&format!(
+                "DELETE FROM files f WHERE f.id = $3 AND {UNUSED_FILES} RETURNING *"
+            ))
+            .bind(ws_id)
+            .bind(grace_period)
+            .bind(candidate.id)
+            .fetch_optional(&mut *tx)
+            .await?;
+            let Some(info) = info else {
+                continue;
+            };
+            sqlx::query("UPDATE workspaces SET storage_used = greatest(storage_used - $2, 0) WHERE id = $1")
+                .bind(info.ws_id)
+                .bind(info.size)
+                .execute(&mut *tx)
+                .await?;
+
+            // the content goes while the row is locked, an upload of the same content waits for
+            // the commit and then stores it again
+            let file = info.chat_file();
+            if let Err(e) = self.store.delete(&file.key()).await {
+                warn!("Failed to remove {}: {}", file.key(), e);
+                continue;
+            }
+            // thumbnails are per content, another extension may still use them
+            let shared: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM files WHERE ws_id = $1 AND hash = $2)")
+                .bind(info.ws_id)
+                .bind(&info.hash)
+                .fetch_one(&mut *tx)
+                .await?;
+            if !shared {
+                for &size in &self.config.server.upload.thumbnail_sizes {
+                    if let Err(e) = self.store.delete(&file.thumbnail_key(size)).await {
+                        warn!("Failed to remove {}: {}", file.thumbnail_key(size), e);
+                    }
+                }
+            }
+            tx.commit().await?;
+            files.push(info.with_url());
+        }
+        let bytes = files.iter().map(|f| f.size).sum();
+        Ok(FileGcReport { dry_run, files, bytes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{ChatFile, CreateMessage};
+
+    #[tokio::test]
+    async fn collect_files_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // the store is shared with other tests, the content must be unique
+        let mut files = vec![];
+        let mut contents = vec![];
+        for name in ["used.txt", "unused.txt"] {
+            let data = format!("{} {}", name, uuid::Uuid::now_v7());
+            let file = ChatFile::new(1, name, data.as_bytes());
+            state
+                .create_file(&file, Bytes::from(data.clone()), name, "text/plain", None, 1)
+                .await?;
+            files.push(file);
+            contents.push(data);
+        }
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            files: vec![files[0].url()],
+            parent_id: None,
+        };
+        state.create_message(input, 1, 1, 1).await?;
+
+        // within the grace period nothing goes
+        let report = state
+            .collect_files(None, Duration::from_secs(3600), false)
+            .await?;
+        assert!(report.files.is_empty());
+
+        let report = state.collect_files(Some(1), Duration::ZERO, true).await?;
+        let urls: Vec<_> = report.files.iter().map(|f| f.url.clone()).collect();
+        assert_eq!(urls, vec![files[1].url()]);
+        assert_eq!(report.bytes, report.files[0].size);
+        assert!(state.store.head(&files[1].key()).await?.is_some());
+
+        // other workspaces are left alone
+        let report = state.collect_files(Some(2), Duration::ZERO, false).await?;
+        assert!(report.files.is_empty());
+
+        let report = state.collect_files(None, Duration::ZERO, false).await?;
+        assert_eq!(report.files.len(), 1);
+        assert!(!report.dry_run);
+        assert_eq!(state.store.head(&files[1].key()).await?, None);
+        assert_eq!(state.find_file_info(&files[1]).await?, None);
+        assert!(state.store.head(&files[0].key()).await?.is_some());
+        let used: i64 = sqlx::query_scalar("SELECT storage_used FROM workspaces WHERE id = 1")
+            .fetch_one(&state.pool)
+            .await?;
+        assert_eq!(used, state.find_file_info(&files[0]).await?.unwrap().size);
+
+        // uploaded again once it went, the content is there again
+        let data = Bytes::from(contents[1].clone());
+        state
+            .create_file(&files[1], data, "unused.txt", "text/plain", None, 1)
+            .await?;
+        assert!(state.store.head(&files[1].key()).await?.is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,307 @@
+use chat_core::{Role, User};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection};
+use utoipa::ToSchema;
+
+use super::token::{hash_token, random_hex};
+use crate::{error::AppError, AppState};
+
+const DEFAULT_INVITE_DURATION: u64 = 60 * 60 * 24 * 7;
+const MAX_INVITE_DURATION: u64 = 60 * 60 * 24 * 90;
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct Invite {
+    pub id: i64,
+    pub ws_id: i64,
+    pub created_by: Option<i64>,
+    /// only this email may sign up with it
+    pub email: Option<String>,
+    /// only emails of this domain may sign up with it
+    pub domain: Option<String>,
+    /// unlimited if unset
+    pub max_uses: Option<i32>,
+    pub uses: i32,
+    /// the role of whoever signs up with it
+    pub role: Role,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    /// only returned when the invite is created, it isn't kept
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct CreateInvite {
+    #[serde(default)]
+    pub email: Option<String>,
+    /// e.g. `acme.org`
+    #[serde(default)]
+    pub domain: Option<String>,
+    /// 1 unless given, `null` for unlimited
+    #[serde(default = "default_max_uses")]
+    pub max_uses: Option<u32>,
+    /// seconds until it expires, a week unless given
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+    /// member unless given, an invite can't make owners
+    #[serde(default)]
+    pub role: Option<Role>,
+}
+
+fn default_max_uses() -> Option<u32> {
+    Some(1)
+}
+
+impl AppState {
+    /// a new invite to the workspace, the token is only part of the returned invite
+    pub async fn create_invite(&self, input: CreateInvite, ws_id: u64, user_id: u64) -> Result<Invite, AppError> {
+        if input.email.as_ref().is_some_and(|v| !v.contains('@')) {
+            return Err(AppError::CreateInviteError("invalid email".to_string()));
+        }
+        if input
+            .domain
+            .as_ref()
+            .is_some_and(|v| v.is_empty() || v.contains('@'))
+        {
+            return Err(AppError::CreateInviteError("invalid domain".to_string()));
+        }
+        if input.max_uses == Some(0) {
+            return Err(AppError::CreateInviteError("max_uses must be positive".to_string()));
+        }
+        let role = input.role.unwrap_or_default();
+        if role == Role::Owner {
+            return Err(AppError::CreateInviteError("an invite can't make owners".to_string()));
+        }
+        let expires_in = input.expires_in.unwrap_or(DEFAULT_INVITE_DURATION);
+        if expires_in == 0 || expires_in > MAX_INVITE_DURATION {
+            return Err(AppError::CreateInviteError(format!(
+                "expires_in must be between 1 and {MAX_INVITE_DURATION} seconds"
+            )));
+        }
+
+        let token = random_hex(32);
+        let mut invite: Invite = sqlx::query_as(
+            r#"
+            INSERT INTO workspace_invites (ws_id, token_hash, created_by, email, domain, max_uses, role, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, now() + make_interval(secs => $8))
+            RETURNING *
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(hash_token(&token))
+        .bind(user_id as i64)
+        .bind(input.email)
+        .bind(input.domain)
+        .bind(input.max_uses.map(|v| v as i32))
+        .bind(role)
+        .bind(expires_in as f64)
+        .fetch_one(&self.pool)
+        .await?;
+        invite.token = Some(token);
+        Ok(invite)
+    }
+
+    /// invites of the workspace which can still be used
+    pub async fn list_invites(&self, ws_id: u64) -> Result<Vec<Invite>, AppError> {
+        let invites = sqlx::query_as(
+            r#"
+            SELECT * FROM workspace_invites
+            WHERE ws_id = $1 AND revoked_at IS NULL AND expires_at > now()
+              AND (max_uses IS NULL OR uses < max_uses)
+            ORDER BY id
+            "#,
+        )
+        .bind(ws_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(invites)
+    }
+
+    pub async fn revoke_invite(&self, id: u64, ws_id: u64) -> Result<(), AppError> {
+        let ret = sqlx::query(
+            "UPDATE workspace_invites SET revoked_at = now() WHERE id = $1 AND ws_id = $2 AND revoked_at IS NULL",
+        )
+        .bind(id as i64)
+        .bind(ws_id as i64)
+        .execute(&self.pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("invite id {id} not found")));
+        }
+        Ok(())
+    }
+
+    /// add an existing user to the workspace of an invite, the user in that workspace.
+    /// joining a workspace they are a member of already doesn't use up the invite.
+    pub async fn join_workspace(&self, token: &str, user_id: u64, email: &str) -> Result<User, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let (ws_id, role) = self.use_invite(token, email, &mut tx).await?;
+        let ret = sqlx::query(
+            "INSERT INTO workspace_members (ws_id, user_id, role) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+        )
+        .bind(ws_id)
+        .bind(user_id as i64)
+        .bind(role)
+        .execute(&mut *tx)
+        .await?;
+        if ret.rows_affected() > 0 {
+            self.join_default_channels(ws_id, user_id as _, &mut tx)
+                .await?;
+            tx.commit().await?;
+        } else {
+            tx.rollback().await?;
+        }
+        self.switch_workspace(user_id, ws_id as _).await
+    }
+
+    /// count a use of the invite by `email`, the workspace and role it's for
+    pub(super) async fn use_invite(
+        &self,
+        token: &str,
+        email: &str,
+        conn: &mut PgConnection,
+    ) -> Result<(i64, Role), AppError> {
+        let ret: Option<(i64, Role)> = sqlx::query_as(
+            r#"
+            UPDATE workspace_invites SET uses = uses + 1
+            WHERE token_hash = $1 AND revoked_at IS NULL AND expires_at > now()
+              AND (max_uses IS NULL OR uses < max_uses)
+              AND (email IS NULL OR lower(email) = lower($2))
+              AND (domain IS NULL OR lower(domain) = lower(split_part($2, '@', 2)))
+            RETURNING ws_id, role
+            "#,
+        )
+        .bind(hash_token(token))
+        .bind(email)
+        .fetch_optional(conn)
+        .await?;
+        ret.ok_or_else(|| AppError::PermissionDenied("invalid or expired invite".to_string()))
+    }
+}
+
+#[cfg(test)]
+impl CreateInvite {
+    pub fn new(max_uses: Option<u32>) -> Self {
+        Self {
+            email: None,
+            domain: None,
+            max_uses,
+            expires_in: None,
+            role: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::CreateUser;
+
+    #[tokio::test]
+    async fn invite_should_be_used_once() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let invite = state
+            .create_invite(CreateInvite::new(Some(1)), 1, 1)
+            .await?;
+        let token = invite.token.clone().expect("token of a new invite");
+        assert_eq!(state.list_invites(1).await?.len(), 1);
+
+        let input = CreateUser::with_invite(&token, "ivy", "ivy@acme.org", "123456");
+        let user = state.create_user(&input).await?;
+        assert_eq!(user.ws_id, 1);
+        assert_eq!(user.role, Role::Member);
+        assert!(state.list_invites(1).await?.is_empty());
+
+        let input = CreateUser::with_invite(&token, "jack", "jack@acme.org", "123456");
+        let err = state.create_user(&input).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+        assert!(state.find_user_by_email("jack@acme.org").await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invite_should_be_bound_to_email_or_domain() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut input = CreateInvite::new(None);
+        input.domain = Some("acme.org".to_string());
+        let token = state.create_invite(input, 1, 1).await?.token.unwrap();
+        let err = state
+            .create_user(&CreateUser::with_invite(&token, "eve", "eve@evil.org", "123456"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+        for name in ["ivy", "jack"] {
+            let email = format!("{name}@ACME.org");
+            let user = state
+                .create_user(&CreateUser::with_invite(&token, name, &email, "123456"))
+                .await?;
+            assert_eq!(user.ws_id, 1);
+        }
+
+        let mut input = CreateInvite::new(Some(1));
+        input.email = Some("carol@acme.org".to_string());
+        let token = state.create_invite(input, 1, 1).await?.token.unwrap();
+        let err = state
+            .create_user(&CreateUser::with_invite(&token, "dave", "dave@acme.org", "123456"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+        state
+            .create_user(&CreateUser::with_invite(&token, "carol", "carol@acme.org", "123456"))
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn revoked_or_expired_invite_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let invite = state.create_invite(CreateInvite::new(None), 1, 1).await?;
+        state.revoke_invite(invite.id as _, 1).await?;
+        let err = state.revoke_invite(invite.id as _, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        let input = CreateUser::with_invite(invite.token.as_ref().unwrap(), "ivy", "ivy@acme.org", "123456");
+        let err = state.create_user(&input).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        let invite = state.create_invite(CreateInvite::new(None), 1, 1).await?;
+        sqlx::query("UPDATE workspace_invites SET expires_at = now() - interval '1 second' WHERE id = $1")
+            .bind(invite.id)
+            .execute(&state.pool)
+            .await?;
+        let input = CreateUser::with_invite(invite.token.as_ref().unwrap(), "ivy", "ivy@acme.org", "123456");
+        let err = state.create_user(&input).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        // another workspace can't revoke it
+        let invite = state.create_invite(CreateInvite::new(None), 1, 1).await?;
+        let err = state.revoke_invite(invite.id as _, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_invite_should_validate_input() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut input = CreateInvite::new(Some(1));
+        input.role = Some(Role::Owner);
+        let err = state.create_invite(input, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::CreateInviteError(_)));
+        let mut input = CreateInvite::new(Some(0));
+        let err = state.create_invite(input.clone(), 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::CreateInviteError(_)));
+        input.max_uses = Some(1);
+        input.email = Some("nobody".to_string());
+        let err = state.create_invite(input.clone(), 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::CreateInviteError(_)));
+        input.email = None;
+        input.expires_in = Some(MAX_INVITE_DURATION + 1);
+        let err = state.create_invite(input, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::CreateInviteError(_)));
+        Ok(())
+    }
+}
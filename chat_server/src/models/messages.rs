@@ -1,16 +1,32 @@
 use std::str::FromStr;
 
-use chat_core::Message;
+use chat_core::{Message, Permission, Role};
 use serde::{Deserialize, Serialize};
 use utoipa::{IntoParams, ToSchema};
 
 use crate::{error::AppError, AppState, ChatFile};
 
+// a live message $1 of chat $2 in workspace $3 the caller $4 may change: they sent it,
+// or they may moderate ($5)
+const CHANGEABLE_MESSAGE: &str = r#"
+    m.id = $1 AND m.chat_id = $2 AND m.deleted_at IS NULL
+    AND EXISTS (SELECT 1 FROM chats c WHERE c.id = m.chat_id AND c.ws_id = $3)
+    AND (m.sender_id = $4 OR $5)
+"#;
+
 #[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
 pub struct CreateMessage {
     pub content: String,
     pub files: Vec<String>,
+    /// reply to this top-level message of the chat
+    #[serde(default)]
+    pub parent_id: Option<u64>,
 }
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct UpdateMessage {
+    pub content: String,
+}
+
 #[derive(Debug, Clone, IntoParams, ToSchema, Serialize, Deserialize)]
 pub struct ListMessages {
     pub last_id: Option<u64>,
@@ -18,51 +34,247 @@ pub struct ListMessages {
 }
 
 impl AppState {
-    pub async fn create_message(&self, input: CreateMessage, chat_id: u64, user_id: u64) -> Result<Message, AppError> {
-        let base_dir = &self.config.server.base_dir;
+    pub async fn create_message(
+        &self,
+        input: CreateMessage,
+        chat_id: u64,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<Message, AppError> {
         //verify content - not empty
         if input.content.is_empty() {
             return Err(AppError::CreateMessageError("Content cannot be empty".to_string()));
         }
-        // verify files exists
         for s in &input.files {
-            let file = ChatFile::from_str(s)?;
-            if !file.path(base_dir).exists() {
-                return Err(AppError::CreateMessageError(format!("File {} does not exist", s)));
+            ChatFile::from_str(s)?;
+        }
+
+        // replies go to live top-level messages of the same chat
+        if let Some(parent_id) = input.parent_id {
+            let parent: Option<i64> = sqlx::query_scalar(
+                r#"
+                SELECT id FROM messages
+                WHERE id = $1 AND chat_id = $2 AND parent_id IS NULL AND deleted_at IS NULL
+                "#,
+            )
+            .bind(parent_id as i64)
+            .bind(chat_id as i64)
+            .fetch_optional(&self.pool)
+            .await?;
+            if parent.is_none() {
+                return Err(AppError::CreateMessageError(format!(
+                    "Parent message {parent_id} does not exist"
+                )));
             }
         }
 
-        // create message
-        let message: Message = sqlx::query_as(
+        // verify files exist, their rows stay locked until the message refers to them
+        // so the file gc can't remove them meanwhile
+        let mut tx = self.pool.begin().await?;
+        let known: Vec<String> = sqlx::query_scalar(
+            "SELECT file_url(f) FROM files f WHERE f.ws_id = $1 AND file_url(f) = ANY($2) FOR SHARE",
+        )
+        .bind(ws_id as i64)
+        .bind(&input.files)
+        .fetch_all(&mut *tx)
+        .await?;
+        if let Some(s) = input.files.iter().find(|s| !known.contains(s)) {
+            return Err(AppError::CreateMessageError(format!("File {} does not exist", s)));
+        }
+
+        // create message, the chat must live in the sender's workspace.
+        // the parent's reply counters are bumped in the same statement.
+        let message: Option<Message> = sqlx::query_as(
             r#"
-            INSERT INTO messages (chat_id, sender_id, content, files)
-            VALUES ($1, $2, $3, $4)
-            RETURNING *
+            WITH m AS (
+                INSERT INTO messages (chat_id, sender_id, content, files, parent_id)
+                SELECT id, $2, $3, $4, $6 FROM chats WHERE id = $1 AND ws_id = $5
+                RETURNING *
+            ), p AS (
+                UPDATE messages SET reply_count = messages.reply_count + 1, last_reply_at = m.created_at
+                FROM m WHERE messages.id = m.parent_id
+            )
+            SELECT * FROM m
             "#,
         )
         .bind(chat_id as i64)
         .bind(user_id as i64)
         .bind(input.content)
         .bind(&input.files)
-        .fetch_one(&self.pool)
+        .bind(ws_id as i64)
+        .bind(input.parent_id.map(|v| v as i64))
+        .fetch_optional(&mut *tx)
         .await?;
 
+        let Some(mut message) = message else {
+            return Err(AppError::NotFound(format!("chat id {chat_id} not found")));
+        };
+        tx.commit().await?;
+        self.attach_files(vec![&mut message]).await?;
         Ok(message)
     }
 
-    pub async fn list_message(&self, input: ListMessages, chat_id: u64) -> Result<Vec<Message>, AppError> {
+    /// edit the content of a message, only the sender or a workspace owner/admin may do so.
+    /// `user_id` and `role` are the caller's.
+    pub async fn update_message(
+        &self,
+        input: UpdateMessage,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+        role: Role,
+        ws_id: u64,
+    ) -> Result<Message, AppError> {
+        if input.content.is_empty() {
+            return Err(AppError::UpdateMessageError("Content cannot be empty".to_string()));
+        }
+        // checked and changed in one statement, it may be deleted meanwhile
+        let message: Option<Message> = sqlx::query_as(&format!(
+            r#"
+            UPDATE messages m SET content = $6, edited_at = now()
+            WHERE {CHANGEABLE_MESSAGE}
+            RETURNING *
+            "#
+        ))
+        .bind(message_id as i64)
+        .bind(chat_id as i64)
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .bind(role.can(Permission::ModerateMessages))
+        .bind(input.content)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(mut message) = message else {
+            return Err(self
+                .message_change_error(chat_id, message_id, user_id, ws_id)
+                .await);
+        };
+        self.attach_files(vec![&mut message]).await?;
+        Ok(message)
+    }
+
+    /// turn the message into a tombstone, only the sender or a workspace owner/admin may do so.
+    /// `user_id` and `role` are the caller's.
+    pub async fn delete_message(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+        role: Role,
+        ws_id: u64,
+    ) -> Result<Message, AppError> {
+        let message: Option<Message> = sqlx::query_as(&format!(
+            r#"
+            UPDATE messages m SET content = '', files = '{{}}', deleted_at = now()
+            WHERE {CHANGEABLE_MESSAGE}
+            RETURNING *
+            "#
+        ))
+        .bind(message_id as i64)
+        .bind(chat_id as i64)
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .bind(role.can(Permission::ModerateMessages))
+        .fetch_optional(&self.pool)
+        .await?;
+        match message {
+            Some(message) => Ok(message),
+            None => Err(self
+                .message_change_error(chat_id, message_id, user_id, ws_id)
+                .await),
+        }
+    }
+
+    /// remove the messages older than the retention period of their workspace, the
+    /// number of messages removed. replies go with their thread.
+    pub async fn purge_expired_messages(&self) -> Result<u64, AppError> {
+        let ret = sqlx::query(
+            r#"
+            DELETE FROM messages m USING chats c, workspaces w
+            WHERE m.chat_id = c.id AND c.ws_id = w.id AND w.retention_days > 0
+              AND m.created_at < now() - make_interval(days => w.retention_days)
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(ret.rows_affected())
+    }
+
+    /// why a message couldn't be changed: it's gone, or someone else's
+    async fn message_change_error(&self, chat_id: u64, message_id: u64, user_id: u64, ws_id: u64) -> AppError {
+        let live: Result<bool, sqlx::Error> = sqlx::query_scalar(
+            r#"
+            SELECT EXISTS(
+              SELECT 1 FROM messages m JOIN chats c ON c.id = m.chat_id
+              WHERE m.id = $1 AND m.chat_id = $2 AND c.ws_id = $3 AND m.deleted_at IS NULL
+            )
+            "#,
+        )
+        .bind(message_id as i64)
+        .bind(chat_id as i64)
+        .bind(ws_id as i64)
+        .fetch_one(&self.pool)
+        .await;
+        match live {
+            Ok(true) => AppError::PermissionDenied(format!("user {user_id} can't change message {message_id}")),
+            Ok(false) => AppError::NotFound(format!("message id {message_id} not found")),
+            Err(e) => e.into(),
+        }
+    }
+
+    pub async fn list_message(
+        &self,
+        input: ListMessages,
+        chat_id: u64,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<Vec<Message>, AppError> {
         let last_id = input.last_id.unwrap_or(i64::MAX as _);
-        let messages: Vec<Message> = sqlx::query_as(
+        let mut messages: Vec<Message> = sqlx::query_as(
             r#"
-            select * from messages where chat_id = $1 and id < $2 order by id desc limit $3
+            select m.* from messages m join chats c on c.id = m.chat_id
+            where m.chat_id = $1 and c.ws_id = $4 and m.parent_id is null and m.id < $2
+            order by m.id desc limit $3
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(last_id as i64)
+        .bind(input.limit as i64)
+        .bind(ws_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        self.attach_reactions(&mut messages, user_id).await?;
+        self.attach_files(messages.iter_mut().collect()).await?;
 
+        Ok(messages)
+    }
+
+    /// replies to a message, newest first with the same cursor as `list_message`
+    pub async fn list_replies(
+        &self,
+        input: ListMessages,
+        chat_id: u64,
+        parent_id: u64,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<Vec<Message>, AppError> {
+        let last_id = input.last_id.unwrap_or(i64::MAX as _);
+        let mut messages: Vec<Message> = sqlx::query_as(
+            r#"
+            select m.* from messages m join chats c on c.id = m.chat_id
+            where m.chat_id = $1 and c.ws_id = $4 and m.parent_id = $5 and m.id < $2
+            order by m.id desc limit $3
             "#,
         )
         .bind(chat_id as i64)
         .bind(last_id as i64)
         .bind(input.limit as i64)
+        .bind(ws_id as i64)
+        .bind(parent_id as i64)
         .fetch_all(&self.pool)
         .await?;
+        self.attach_reactions(&mut messages, user_id).await?;
+        self.attach_files(messages.iter_mut().collect()).await?;
 
         Ok(messages)
     }
@@ -72,8 +284,10 @@ impl AppState {
 mod tests {
 
     use anyhow::Result;
+    use bytes::Bytes;
 
     use super::*;
+    use crate::storage::FileStore;
 
     #[tokio::test]
     async fn create_message_should_work() -> Result<()> {
@@ -81,9 +295,10 @@ mod tests {
         let input = CreateMessage {
             content: "hello".to_string(),
             files: vec![],
+            parent_id: None,
         };
         let message = state
-            .create_message(input, 1, 1)
+            .create_message(input, 1, 1, 1)
             .await
             .expect("create message failed");
         assert_eq!(message.content, "hello");
@@ -92,24 +307,59 @@ mod tests {
         let input = CreateMessage {
             content: "hello".to_string(),
             files: vec!["1".to_string()],
+            parent_id: None,
         };
 
-        let err = state.create_message(input, 1, 1).await.unwrap_err();
+        let err = state.create_message(input, 1, 1, 1).await.unwrap_err();
         assert_eq!(err.to_string(), "Invalid chat file path: 1");
 
         // valid files should work
-        let url = upload_dummy_file(&state)?;
+        let url = upload_dummy_file(&state).await?;
         let input = CreateMessage {
             content: "hello".to_string(),
             files: vec![url],
+            parent_id: None,
         };
         let message = state
-            .create_message(input, 1, 1)
+            .create_message(input, 1, 1, 1)
             .await
             .expect("create message failed");
         assert_eq!(message.content, "hello");
         assert_eq!(message.files.len(), 1);
 
+        // content without a file record, e.g. collected meanwhile, should fail
+        let file = ChatFile::new(1, "gone.txt", b"collected");
+        state
+            .store
+            .put(&file.key(), Bytes::from_static(b"collected"))
+            .await?;
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            files: vec![file.url()],
+            parent_id: None,
+        };
+        let err = state.create_message(input, 1, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::CreateMessageError(_)));
+
+        // files from another workspace should fail
+        let file = ChatFile::new(2, "test.txt", b"hello world");
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            files: vec![file.url()],
+            parent_id: None,
+        };
+        let err = state.create_message(input, 1, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::CreateMessageError(_)));
+
+        // chat from another workspace should fail
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            files: vec![],
+            parent_id: None,
+        };
+        let err = state.create_message(input, 5, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
         Ok(())
     }
 
@@ -121,7 +371,7 @@ mod tests {
             limit: 6,
         };
 
-        let messages = state.list_message(input, 1).await?;
+        let messages = state.list_message(input, 1, 1, 1).await?;
         assert_eq!(messages.len(), 6);
 
         let last_id = messages.last().expect("last message should exists").id;
@@ -130,16 +380,201 @@ mod tests {
             last_id: Some(last_id as _),
             limit: 6,
         };
-        let messages = state.list_message(input, 1).await?;
+        let messages = state.list_message(input, 1, 1, 1).await?;
         assert_eq!(messages.len(), 4);
+
+        // chat 5 belongs to workspace 2
+        let input = ListMessages {
+            last_id: None,
+            limit: 6,
+        };
+        let messages = state.list_message(input, 5, 1, 1).await?;
+        assert!(messages.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replies_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let reply = |content: &str, parent_id| CreateMessage {
+            content: content.to_string(),
+            files: vec![],
+            parent_id: Some(parent_id),
+        };
+        let r1 = state.create_message(reply("r1", 1), 1, 2, 1).await?;
+        let r2 = state.create_message(reply("r2", 1), 1, 3, 1).await?;
+        assert_eq!(r1.parent_id, Some(1));
+
+        let input = ListMessages {
+            last_id: None,
+            limit: 10,
+        };
+        let replies = state.list_replies(input.clone(), 1, 1, 1, 1).await?;
+        let ids: Vec<_> = replies.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![r2.id, r1.id]);
+        let input2 = ListMessages {
+            last_id: Some(r2.id as _),
+            limit: 10,
+        };
+        assert_eq!(state.list_replies(input2, 1, 1, 1, 1).await?.len(), 1);
+
+        // replies stay out of the chat's top-level list, the parent carries the counters
+        let messages = state.list_message(input.clone(), 1, 1, 1).await?;
+        assert_eq!(messages.len(), 10);
+        let parent = messages
+            .iter()
+            .find(|m| m.id == 1)
+            .expect("parent should exist");
+        assert_eq!(parent.reply_count, 2);
+        assert_eq!(parent.last_reply_at, Some(r2.created_at));
+
+        // no nested threads, no replies across chats
+        let err = state
+            .create_message(reply("r3", r1.id as _), 1, 1, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::CreateMessageError(_)));
+        let err = state
+            .create_message(reply("r3", 1), 3, 1, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::CreateMessageError(_)));
+        assert!(state.list_replies(input, 1, 1, 1, 2).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_message_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = UpdateMessage {
+            content: "edited".to_string(),
+        };
+        // message 2 was sent by user 2 to chat 1
+        let message = state
+            .update_message(input.clone(), 1, 2, 2, Role::Member, 1)
+            .await?;
+        assert_eq!(message.content, "edited");
+        assert!(message.edited_at.is_some());
+
+        // user 1 owns workspace 1
+        let message = state
+            .update_message(input.clone(), 1, 2, 1, Role::Owner, 1)
+            .await?;
+        assert_eq!(message.content, "edited");
+
+        let err = state
+            .update_message(input.clone(), 1, 2, 3, Role::Member, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        // wrong chat or workspace
+        let err = state
+            .update_message(input.clone(), 2, 2, 2, Role::Member, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        let err = state
+            .update_message(input.clone(), 1, 2, 2, Role::Member, 2)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        let input = UpdateMessage {
+            content: "".to_string(),
+        };
+        let err = state
+            .update_message(input, 1, 2, 2, Role::Member, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::UpdateMessageError(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_message_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let err = state
+            .delete_message(1, 3, 2, Role::Member, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        let message = state.delete_message(1, 3, 3, Role::Member, 1).await?;
+        assert!(message.deleted_at.is_some());
+        assert_eq!(message.content, "");
+
+        // the tombstone stays in the list
+        let input = ListMessages {
+            last_id: Some(4),
+            limit: 1,
+        };
+        let messages = state.list_message(input, 1, 1, 1).await?;
+        assert_eq!(messages[0].id, 3);
+        assert!(messages[0].deleted_at.is_some());
+
+        // deleted messages can't be changed anymore
+        let err = state
+            .delete_message(1, 3, 3, Role::Member, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        let input = UpdateMessage {
+            content: "edited".to_string(),
+        };
+        let err = state
+            .update_message(input, 1, 3, 3, Role::Member, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expired_messages_should_be_purged() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        assert_eq!(state.purge_expired_messages().await?, 0);
+        sqlx::query("UPDATE messages SET created_at = now() - interval '31 days' WHERE id IN (1, 2, 11)")
+            .execute(&state.pool)
+            .await?;
+        sqlx::query("UPDATE workspaces SET retention_days = 30 WHERE id = 1")
+            .execute(&state.pool)
+            .await?;
+        // message 11 is of foo, which keeps its messages
+        assert_eq!(state.purge_expired_messages().await?, 2);
+        let ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM messages WHERE id IN (1, 2, 3, 11) ORDER BY id")
+            .fetch_all(&state.pool)
+            .await?;
+        assert_eq!(ids, vec![3, 11]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn admin_should_delete_others_messages() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // message 4 was sent by user 4, user 5 is a member
+        let err = state
+            .delete_message(1, 4, 5, Role::Member, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+        let message = state.delete_message(1, 4, 5, Role::Admin, 1).await?;
+        assert!(message.deleted_at.is_some());
         Ok(())
     }
 
-    fn upload_dummy_file(state: &AppState) -> Result<String> {
+    async fn upload_dummy_file(state: &AppState) -> Result<String> {
         let file = ChatFile::new(1, "test.txt", b"hello world");
-        let path = file.path(&state.config.server.base_dir);
-        std::fs::create_dir_all(path.parent().expect("file path parent should exists"))?;
-        std::fs::write(&path, b"hello world").expect("write file should work");
+        state
+            .create_file(
+                &file,
+                Bytes::from_static(b"hello world"),
+                "test.txt",
+                "text/plain",
+                None,
+                1,
+            )
+            .await?;
         Ok(file.url())
     }
 }
@@ -1,13 +1,26 @@
 mod chat;
 mod file;
+mod gc;
+mod invite;
 mod messages;
+mod reaction;
+mod read;
+mod search;
+mod token;
 mod user;
 mod workspace;
 
-pub use chat::CreateChat;
-pub use messages::{CreateMessage, ListMessages};
+pub use chat::{AddChatMembers, CreateChat, PublicChannel, UpdateChat};
+pub use file::FileInfo;
+pub use gc::{CollectFiles, FileGcReport};
+pub use invite::{CreateInvite, Invite};
+pub use messages::{CreateMessage, ListMessages, UpdateMessage};
+pub use read::MarkRead;
+pub use search::{SearchHit, SearchMessages};
 use serde::{Deserialize, Serialize};
+pub use token::RefreshInput;
 pub use user::{CreateUser, SigninUser};
+pub use workspace::{JoinWorkspace, UpdateWorkspace, UserWorkspace, WorkspaceMember};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatFile {
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+
+use chat_core::{Message, Reaction};
+
+use crate::{error::AppError, AppState};
+
+const MAX_EMOJI_LEN: usize = 32;
+
+impl AppState {
+    /// react to a message with `emoji`, reacting twice with the same emoji is a no-op.
+    /// returns the reactions of the message afterwards.
+    pub async fn add_reaction(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+        emoji: &str,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<Vec<Reaction>, AppError> {
+        verify_emoji(emoji)?;
+        self.verify_reaction_message(chat_id, message_id, ws_id)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO message_reactions (message_id, user_id, emoji)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(message_id as i64)
+        .bind(user_id as i64)
+        .bind(emoji)
+        .execute(&self.pool)
+        .await?;
+        self.get_reactions(message_id, user_id).await
+    }
+
+    /// take back a reaction, removing one the user hasn't made is a no-op
+    pub async fn remove_reaction(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+        emoji: &str,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<Vec<Reaction>, AppError> {
+        self.verify_reaction_message(chat_id, message_id, ws_id)
+            .await?;
+        sqlx::query("DELETE FROM message_reactions WHERE message_id = $1 AND user_id = $2 AND emoji = $3")
+            .bind(message_id as i64)
+            .bind(user_id as i64)
+            .bind(emoji)
+            .execute(&self.pool)
+            .await?;
+        self.get_reactions(message_id, user_id).await
+    }
+
+    async fn get_reactions(&self, message_id: u64, user_id: u64) -> Result<Vec<Reaction>, AppError> {
+        let mut reactions = self.load_reactions(&[message_id as i64], user_id).await?;
+        Ok(reactions.remove(&(message_id as i64)).unwrap_or_default())
+    }
+
+    /// fill in the reactions of the messages as seen by `user_id`
+    pub(crate) async fn attach_reactions(&self, messages: &mut [Message], user_id: u64) -> Result<(), AppError> {
+        if messages.is_empty() {
+            return Ok(());
+        }
+        let ids: Vec<i64> = messages.iter().map(|m| m.id).collect();
+        let mut reactions = self.load_reactions(&ids, user_id).await?;
+        for message in messages.iter_mut() {
+            message.reactions = reactions.remove(&message.id).unwrap_or_default();
+        }
+        Ok(())
+    }
+
+    /// reactions per message, each emoji in the order it was first used
+    async fn load_reactions(&self, ids: &[i64], user_id: u64) -> Result<HashMap<i64, Vec<Reaction>>, AppError> {
+        let rows: Vec<(i64, String, i64, bool)> = sqlx::query_as(
+            r#"
+            SELECT message_id, emoji, count(*), bool_or(user_id = $2)
+            FROM message_reactions
+            WHERE message_id = ANY($1)
+            GROUP BY message_id, emoji
+            ORDER BY message_id, min(created_at), emoji
+            "#,
+        )
+        .bind(ids)
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut reactions: HashMap<i64, Vec<Reaction>> = HashMap::new();
+        for (message_id, emoji, count, me) in rows {
+            reactions
+                .entry(message_id)
+                .or_default()
+                .push(Reaction { emoji, count, me });
+        }
+        Ok(reactions)
+    }
+
+    async fn verify_reaction_message(&self, chat_id: u64, message_id: u64, ws_id: u64) -> Result<(), AppError> {
+        let id: Option<i64> = sqlx::query_scalar(
+            r#"
+            SELECT m.id FROM messages m JOIN chats c ON c.id = m.chat_id
+            WHERE m.id = $1 AND m.chat_id = $2 AND c.ws_id = $3 AND m.deleted_at IS NULL
+            "#,
+        )
+        .bind(message_id as i64)
+        .bind(chat_id as i64)
+        .bind(ws_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        match id {
+            Some(_) => Ok(()),
+            None => Err(AppError::NotFound(format!("message id {message_id} not found"))),
+        }
+    }
+}
+
+fn verify_emoji(emoji: &str) -> Result<(), AppError> {
+    if emoji.is_empty() || emoji.chars().count() > MAX_EMOJI_LEN || emoji.chars().any(char::is_whitespace) {
+        return Err(AppError::ReactionError(format!("invalid emoji: {emoji:?}")));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use chat_core::Role;
+
+    use super::*;
+    use crate::ListMessages;
+
+    #[tokio::test]
+    async fn reactions_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let reactions = state.add_reaction(1, 1, "👍", 1, 1).await?;
+        assert_eq!(reactions.len(), 1);
+        assert_eq!(reactions[0].count, 1);
+        assert!(reactions[0].me);
+
+        // reacting twice counts once
+        state.add_reaction(1, 1, "👍", 1, 1).await?;
+        state.add_reaction(1, 1, "🎉", 2, 1).await?;
+        let reactions = state.add_reaction(1, 1, "👍", 2, 1).await?;
+        let counts: Vec<_> = reactions
+            .iter()
+            .map(|r| (r.emoji.as_str(), r.count))
+            .collect();
+        assert_eq!(counts, vec![("👍", 2), ("🎉", 1)]);
+
+        // embedded in the message list as seen by the caller
+        let input = ListMessages {
+            last_id: None,
+            limit: 100,
+        };
+        let messages = state.list_message(input, 1, 3, 1).await?;
+        let message = messages.iter().find(|m| m.id == 1).expect("message 1");
+        assert_eq!(message.reactions.len(), 2);
+        assert!(message.reactions.iter().all(|r| !r.me));
+        assert!(messages
+            .iter()
+            .filter(|m| m.id != 1)
+            .all(|m| m.reactions.is_empty()));
+
+        let reactions = state.remove_reaction(1, 1, "👍", 1, 1).await?;
+        assert_eq!(reactions[0].count, 1);
+        assert!(!reactions[0].me);
+        let reactions = state.remove_reaction(1, 1, "🎉", 2, 1).await?;
+        assert_eq!(reactions.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invalid_reactions_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let err = state.add_reaction(1, 1, "", 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::ReactionError(_)));
+        let err = state
+            .add_reaction(1, 1, "thumbs up", 1, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::ReactionError(_)));
+
+        // message of another chat, or a chat of another workspace
+        let err = state.add_reaction(2, 1, "👍", 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        let err = state.add_reaction(1, 1, "👍", 1, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        // deleted messages can't be reacted to
+        state.delete_message(1, 1, 1, Role::Owner, 1).await?;
+        let err = state.add_reaction(1, 1, "👍", 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+}
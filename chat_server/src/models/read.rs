@@ -0,0 +1,89 @@
+use chat_core::ChatRead;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{error::AppError, AppState};
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct MarkRead {
+    /// everything up to and including this message has been read
+    pub message_id: u64,
+}
+
+impl AppState {
+    /// advance the user's read position in the chat, it never moves backwards
+    pub async fn mark_read(
+        &self,
+        input: MarkRead,
+        chat_id: u64,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<ChatRead, AppError> {
+        let read = ChatRead::mark(
+            &self.pool,
+            chat_id as _,
+            user_id as _,
+            ws_id as _,
+            input.message_id as _,
+        )
+        .await?;
+        read.ok_or_else(|| AppError::NotFound(format!("message id {} not found", input.message_id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn mark_read_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let unread = |chats: &[chat_core::Chat]| chats.iter().find(|c| c.id == 1).map(|c| c.unread);
+        let before = unread(&state.fetch_chats(1, 1).await?).expect("chat 1");
+        assert!(before > 0);
+
+        let read = state.mark_read(MarkRead { message_id: 5 }, 1, 1, 1).await?;
+        assert_eq!(read.last_read_message_id, 5);
+        let after = unread(&state.fetch_chats(1, 1).await?).expect("chat 1");
+        assert!(after < before);
+
+        // it never moves backwards
+        let read = state.mark_read(MarkRead { message_id: 2 }, 1, 1, 1).await?;
+        assert_eq!(read.last_read_message_id, 5);
+
+        let chats = state.fetch_chats(1, 1).await?;
+        let last_id = chats[0].last_message.as_ref().expect("last message").id;
+        state
+            .mark_read(
+                MarkRead {
+                    message_id: last_id as _,
+                },
+                1,
+                1,
+                1,
+            )
+            .await?;
+        assert_eq!(unread(&state.fetch_chats(1, 1).await?), Some(0));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mark_read_should_be_limited_to_members() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // message of another chat
+        let err = state
+            .mark_read(MarkRead { message_id: 1 }, 2, 1, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        // chat of another workspace
+        let err = state
+            .mark_read(MarkRead { message_id: 1 }, 1, 6, 2)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+use chat_core::Message;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{error::AppError, AppState};
+
+const DEFAULT_SEARCH_LIMIT: u64 = 20;
+const MAX_SEARCH_LIMIT: u64 = 100;
+
+#[derive(Debug, Clone, Default, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct SearchMessages {
+    /// words to look for, in `websearch_to_tsquery` syntax (e.g. `"exact phrase" -skip`)
+    pub q: String,
+    pub chat_id: Option<u64>,
+    pub sender_id: Option<u64>,
+    /// only messages sent at or after this time
+    pub from: Option<DateTime<Utc>>,
+    /// only messages sent before this time
+    pub to: Option<DateTime<Utc>>,
+    pub has_files: Option<bool>,
+    pub last_id: Option<u64>,
+    pub limit: Option<u64>,
+}
+
+/// a matching message with the matches highlighted as `<b>..</b>`, the rest of the snippet
+/// is html escaped
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize)]
+pub struct SearchHit {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub message: Message,
+    pub snippet: String,
+}
+
+impl AppState {
+    /// search the messages of the chats the user is a member of, newest first.
+    /// pass the id of the last hit as `last_id` to get the next page.
+    pub async fn search_messages(
+        &self,
+        input: SearchMessages,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<Vec<SearchHit>, AppError> {
+        let q = input.q.trim();
+        if q.is_empty() {
+            return Err(AppError::SearchError("query cannot be empty".to_string()));
+        }
+        let chat_ids = self.member_chat_ids(user_id, ws_id, input.chat_id).await?;
+        if chat_ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let last_id = input.last_id.unwrap_or(i64::MAX as _);
+        let limit = input
+            .limit
+            .unwrap_or(DEFAULT_SEARCH_LIMIT)
+            .min(MAX_SEARCH_LIMIT);
+
+        let mut hits: Vec<SearchHit> = sqlx::query_as(
+            r#"
+            select m.*, ts_headline(
+                'simple',
+                -- escaped first, the <b> tags are the only markup of the snippet
+                replace(replace(replace(m.content, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'),
+                query,
+                'StartSel=<b>, StopSel=</b>, MaxFragments=2'
+            ) as snippet
+            from messages m, websearch_to_tsquery('simple', $2) query
+            where m.chat_id = any($1) and to_tsvector('simple', m.content) @@ query
+            and m.deleted_at is null and m.id < $3
+            and ($4::bigint is null or m.sender_id = $4)
+            and ($5::timestamptz is null or m.created_at >= $5)
+            and ($6::timestamptz is null or m.created_at < $6)
+            and ($7::boolean is null or (cardinality(m.files) > 0) = $7)
+            order by m.id desc limit $8
+            "#,
+        )
+        .bind(&chat_ids)
+        .bind(q)
+        .bind(last_id as i64)
+        .bind(input.sender_id.map(|v| v as i64))
+        .bind(input.from)
+        .bind(input.to)
+        .bind(input.has_files)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        self.attach_files(hits.iter_mut().map(|h| &mut h.message).collect())
+            .await?;
+
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use chat_core::Role;
+
+    use super::*;
+    use crate::CreateMessage;
+
+    fn search(q: &str) -> SearchMessages {
+        SearchMessages {
+            q: q.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn search_messages_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let hits = state.search_messages(search("hello"), 1, 1).await?;
+        let ids: Vec<_> = hits.iter().map(|h| h.message.id).collect();
+        assert_eq!(ids, vec![10, 9, 6, 1]);
+        assert_eq!(hits[0].snippet, "<b>Hello</b>, world");
+
+        // filters and pagination
+        let input = SearchMessages {
+            sender_id: Some(2),
+            ..search("there")
+        };
+        assert_eq!(state.search_messages(input, 1, 1).await?.len(), 2);
+        let input = SearchMessages {
+            last_id: Some(9),
+            limit: Some(1),
+            ..search("hello")
+        };
+        let hits = state.search_messages(input, 1, 1).await?;
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].message.id, 6);
+        let input = SearchMessages {
+            has_files: Some(true),
+            ..search("hello")
+        };
+        assert!(state.search_messages(input, 1, 1).await?.is_empty());
+        let input = SearchMessages {
+            from: Some(Utc::now() + chrono::Duration::hours(1)),
+            ..search("hello")
+        };
+        assert!(state.search_messages(input, 1, 1).await?.is_empty());
+
+        // deleted messages are gone
+        state.delete_message(1, 10, 1, Role::Owner, 1).await?;
+        assert_eq!(state.search_messages(search("hello"), 1, 1).await?.len(), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_should_be_limited_to_member_chats() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "secret plans".to_string(),
+            files: vec![],
+            parent_id: None,
+        };
+        state.create_message(input, 2, 1, 1).await?;
+        assert_eq!(state.search_messages(search("secret"), 1, 1).await?.len(), 1);
+        // user 4 isn't in chat 2
+        assert!(state
+            .search_messages(search("secret"), 4, 1)
+            .await?
+            .is_empty());
+        let input = SearchMessages {
+            chat_id: Some(2),
+            ..search("secret")
+        };
+        assert!(state.search_messages(input, 4, 1).await?.is_empty());
+        // other workspaces
+        assert!(state.search_messages(search("foo"), 1, 1).await?.is_empty());
+
+        let err = state.search_messages(search("  "), 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::SearchError(_)));
+        Ok(())
+    }
+    #[tokio::test]
+    async fn search_snippet_should_be_escaped() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "<script>alert(1)</script> & bonjour".to_string(),
+            files: vec![],
+            parent_id: None,
+        };
+        state.create_message(input, 1, 1, 1).await?;
+        let hits = state.search_messages(search("bonjour"), 1, 1).await?;
+        let snippet = &hits[0].snippet;
+        assert!(
+            !snippet.contains("<script") && !snippet.contains("</script"),
+            "{snippet}"
+        );
+        assert!(
+            snippet.ends_with("alert(1)&lt;/script&gt; &amp; <b>bonjour</b>"),
+            "{snippet}"
+        );
+        assert_eq!(hits[0].message.content, "<script>alert(1)</script> & bonjour");
+        Ok(())
+    }
+}
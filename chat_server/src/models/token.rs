@@ -0,0 +1,240 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chat_core::User;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::{error::AppError, AppState};
+
+const REFRESH_TOKEN_DURATION: i64 = 60 * 60 * 24 * 30;
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct RefreshInput {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Clone, FromRow)]
+#[allow(unused)]
+struct RefreshToken {
+    id: i64,
+    user_id: i64,
+    ws_id: i64,
+    family: String,
+    expires_at: DateTime<Utc>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+impl AppState {
+    /// issue a refresh token for the workspace starting a new family (signin / signup / switch)
+    pub async fn create_refresh_token(&self, user_id: u64, ws_id: u64) -> Result<String, AppError> {
+        let family = random_hex(16);
+        self.insert_refresh_token(user_id, ws_id, &family, &self.pool)
+            .await
+    }
+
+    /// exchange a refresh token for the user, in the workspace it was issued for, and a new
+    /// refresh token of the same family.
+    /// presenting a token which has already been used revokes the whole family.
+    pub async fn rotate_refresh_token(&self, token: &str) -> Result<(User, String), AppError> {
+        let hash = hash_token(token);
+        let mut tx = self.pool.begin().await?;
+        let current: Option<RefreshToken> = sqlx::query_as(
+            r#"
+            UPDATE refresh_tokens SET revoked_at = now()
+            WHERE token_hash = $1 AND revoked_at IS NULL AND expires_at > now()
+            RETURNING id, user_id, ws_id, family, expires_at, revoked_at
+            "#,
+        )
+        .bind(&hash)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(current) = current else {
+            let reused: Option<RefreshToken> = sqlx::query_as(
+                r#"
+                SELECT id, user_id, ws_id, family, expires_at, revoked_at
+                FROM refresh_tokens WHERE token_hash = $1 AND revoked_at IS NOT NULL
+                "#,
+            )
+            .bind(&hash)
+            .fetch_optional(&mut *tx)
+            .await?;
+            if let Some(reused) = reused {
+                warn!(
+                    "refresh token {} of user {} reused, revoking family",
+                    reused.id, reused.user_id
+                );
+                self.revoke_refresh_token_family(&reused.family, &mut *tx)
+                    .await?;
+                tx.commit().await?;
+            }
+            return Err(AppError::Unauthorized("invalid refresh token".to_string()));
+        };
+
+        let token = self
+            .insert_refresh_token(current.user_id as _, current.ws_id as _, &current.family, &mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        // they may have left the workspace meanwhile
+        match self
+            .find_member(current.user_id as _, current.ws_id as _)
+            .await?
+        {
+            Some(user) => Ok((user, token)),
+            None => Err(AppError::Unauthorized("invalid refresh token".to_string())),
+        }
+    }
+
+    /// revoke the refresh token and every token rotated from the same signin, if it's one of `user_id`
+    pub async fn revoke_refresh_token(&self, token: &str, user_id: u64) -> Result<(), AppError> {
+        let family: Option<String> =
+            sqlx::query_scalar("SELECT family FROM refresh_tokens WHERE token_hash = $1 AND user_id = $2")
+                .bind(hash_token(token))
+                .bind(user_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+        if let Some(family) = family {
+            self.revoke_refresh_token_family(&family, &self.pool)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// put an access token on the deny list until it expires
+    pub async fn revoke_access_token(&self, jti: &str, expires_at: DateTime<Utc>) -> Result<(), AppError> {
+        // expired entries are useless, clean them up on the way
+        sqlx::query("DELETE FROM revoked_tokens WHERE expires_at < now()")
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("INSERT INTO revoked_tokens (jti, expires_at) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+            .bind(jti)
+            .bind(expires_at)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// whether an access token of `user` can't be used anymore, see `User::is_token_revoked`
+    pub async fn is_token_revoked(&self, jti: Option<&str>, user: &User) -> Result<bool, AppError> {
+        Ok(user.is_token_revoked(&self.pool, jti).await?)
+    }
+
+    async fn insert_refresh_token<'e, E>(
+        &self,
+        user_id: u64,
+        ws_id: u64,
+        family: &str,
+        executor: E,
+    ) -> Result<String, AppError>
+    where
+        E: sqlx::PgExecutor<'e>,
+    {
+        let token = random_hex(32);
+        sqlx::query(
+            r#"
+            WITH seen AS (
+              UPDATE workspace_members SET last_seen_at = now() WHERE user_id = $1 AND ws_id = $2
+            )
+            INSERT INTO refresh_tokens (user_id, ws_id, token_hash, family, expires_at)
+            VALUES ($1, $2, $3, $4, now() + make_interval(secs => $5))
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(ws_id as i64)
+        .bind(hash_token(&token))
+        .bind(family)
+        .bind(REFRESH_TOKEN_DURATION as f64)
+        .execute(executor)
+        .await?;
+        Ok(token)
+    }
+
+    async fn revoke_refresh_token_family<'e, E>(&self, family: &str, executor: E) -> Result<(), AppError>
+    where
+        E: sqlx::PgExecutor<'e>,
+    {
+        sqlx::query("UPDATE refresh_tokens SET revoked_at = now() WHERE family = $1 AND revoked_at IS NULL")
+            .bind(family)
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+}
+
+pub(super) fn random_hex(len: usize) -> String {
+    let mut buf = vec![0u8; len];
+    OsRng.fill_bytes(&mut buf);
+    hex::encode(buf)
+}
+
+pub(super) fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn rotate_refresh_token_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let token = state.create_refresh_token(1, 1).await?;
+        let (user, token2) = state.rotate_refresh_token(&token).await?;
+        assert_eq!(user.id, 1);
+        assert_ne!(token, token2);
+
+        let (_, token3) = state.rotate_refresh_token(&token2).await?;
+
+        // reusing a rotated token revokes the whole family
+        let err = state.rotate_refresh_token(&token).await.unwrap_err();
+        assert!(matches!(err, AppError::Unauthorized(_)));
+        let err = state.rotate_refresh_token(&token3).await.unwrap_err();
+        assert!(matches!(err, AppError::Unauthorized(_)));
+
+        // other families are not affected
+        let token = state.create_refresh_token(1, 1).await?;
+        assert!(state.rotate_refresh_token(&token).await.is_ok());
+
+        let err = state.rotate_refresh_token("bad token").await.unwrap_err();
+        assert!(matches!(err, AppError::Unauthorized(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn revoke_refresh_token_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let token = state.create_refresh_token(1, 1).await?;
+        let (_, token2) = state.rotate_refresh_token(&token).await?;
+        // only its owner can revoke it
+        state.revoke_refresh_token(&token2, 2).await?;
+        let (_, token2) = state.rotate_refresh_token(&token2).await?;
+        state.revoke_refresh_token(&token2, 1).await?;
+        let err = state.rotate_refresh_token(&token2).await.unwrap_err();
+        assert!(matches!(err, AppError::Unauthorized(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn revoke_access_token_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(2).await?.expect("user should exist");
+        assert!(!state.is_token_revoked(Some("jti"), &user).await?);
+        let expires_at = Utc::now() + chrono::Duration::minutes(15);
+        state.revoke_access_token("jti", expires_at).await?;
+        assert!(state.is_token_revoked(Some("jti"), &user).await?);
+        // revoking twice is fine
+        state.revoke_access_token("jti", expires_at).await?;
+
+        // all tokens of a deactivated member are
+        assert!(!state.is_token_revoked(None, &user).await?);
+        state.deactivate_member(1, 2).await?;
+        assert!(state.is_token_revoked(None, &user).await?);
+        Ok(())
+    }
+}
@@ -5,18 +5,37 @@ use argon2::{
     password_hash::{rand_core::OsRng, SaltString},
     Argon2, PasswordHash, PasswordVerifier,
 };
-use chat_core::{ChatUser, User};
+use chat_core::{ChatUser, Role, User};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
+use super::workspace::valid_workspace_name;
 use crate::error::AppError;
 use crate::AppState;
 
+// `User` in the workspace they sign in to by default, the one they used last if they are
+// still a member of it
+const USER_IN_DEFAULT_WORKSPACE: &str = r#"
+    SELECT u.id, m.ws_id, u.fullname, u.email, u.password_hash, m.role, u.created_at
+    FROM users u
+    JOIN LATERAL (
+      SELECT ws_id, role FROM workspace_members
+      WHERE user_id = u.id AND deactivated_at IS NULL
+      ORDER BY ws_id = u.ws_id DESC, joined_at
+      LIMIT 1
+    ) m ON true
+"#;
+
 #[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
 pub struct CreateUser {
     pub fullname: String,
     pub email: String,
-    pub workspace: String,
+    /// name of a new workspace to create and own
+    #[serde(default)]
+    pub workspace: Option<String>,
+    /// token of an invite to an existing workspace
+    #[serde(default)]
+    pub invite: Option<String>,
     pub password: String,
 }
 
@@ -29,44 +48,78 @@ pub struct SigninUser {
 #[allow(dead_code)]
 impl AppState {
     pub async fn find_user_by_email(&self, email: &str) -> Result<Option<User>, AppError> {
-        let user = sqlx::query_as("SELECT * FROM users WHERE email=$1")
+        let user = sqlx::query_as(&format!("{USER_IN_DEFAULT_WORKSPACE} WHERE u.email = $1"))
             .bind(email)
             .fetch_optional(&self.pool)
             .await?;
         Ok(user)
     }
 
+    /// sign up into a new workspace the user will own, or into the workspace of an invite
     pub async fn create_user(&self, input: &CreateUser) -> Result<User, AppError> {
         let password_hash = hash_password(&input.password)?;
         // check if email already exists
-        let user = self.find_user_by_email(&input.email).await?;
-        if user.is_some() {
+        let exists: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM users WHERE email = $1)")
+            .bind(&input.email)
+            .fetch_one(&self.pool)
+            .await?;
+        if exists {
             return Err(AppError::EmailAlreadyExists(input.email.clone()));
         }
-        let ws = match self.find_workspace_by_name(&input.workspace).await? {
-            Some(ws) => ws,
-            None => self.create_workspace(&input.workspace, 0).await?,
+
+        let mut tx = self.pool.begin().await?;
+        let (ws_id, role) = match (&input.workspace, &input.invite) {
+            (Some(name), None) => {
+                let name = valid_workspace_name(name)
+                    .ok_or_else(|| AppError::SignupError("workspace name must be 1 to 32 characters".to_string()))?;
+                let id: Option<i64> = sqlx::query_scalar(
+                    "INSERT INTO workspaces (name, owner_id) VALUES ($1, 0) ON CONFLICT (name) DO NOTHING RETURNING id",
+                )
+                .bind(name)
+                .fetch_optional(&mut *tx)
+                .await?;
+                let id = id.ok_or_else(|| AppError::WorkspaceAlreadyExists(name.to_string()))?;
+                (id, Role::Owner)
+            }
+            (None, Some(token)) => self.use_invite(token, &input.email, &mut tx).await?,
+            _ => {
+                return Err(AppError::SignupError(
+                    "either a new workspace or an invite is required".to_string(),
+                ))
+            }
         };
 
-        let user: User = sqlx::query_as(
-            "INSERT INTO users (ws_id,email, fullname, password_hash) VALUES ($1, $2, $3,$4) RETURNING *",
+        let mut user: User = sqlx::query_as(
+            "INSERT INTO users (ws_id, email, fullname, password_hash) VALUES ($1, $2, $3, $4) RETURNING *",
         )
-        .bind(ws.id)
+        .bind(ws_id)
         .bind(&input.email)
         .bind(&input.fullname)
         .bind(password_hash)
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
         .await?;
+        sqlx::query("INSERT INTO workspace_members (ws_id, user_id, role) VALUES ($1, $2, $3)")
+            .bind(ws_id)
+            .bind(user.id)
+            .bind(role)
+            .execute(&mut *tx)
+            .await?;
+        user.role = role;
+        self.join_default_channels(ws_id, user.id, &mut tx).await?;
 
-        if ws.owner_id == 0 {
-            self.update_workspace_owner(ws.id as _, user.id as _)
+        if role == Role::Owner {
+            sqlx::query("UPDATE workspaces SET owner_id = $1 WHERE id = $2")
+                .bind(user.id)
+                .bind(ws_id)
+                .execute(&mut *tx)
                 .await?;
         }
+        tx.commit().await?;
         Ok(user)
     }
 
     pub async fn verify_user(&self, input: &SigninUser) -> Result<Option<User>, AppError> {
-        let user: Option<User> = sqlx::query_as("SELECT * FROM users WHERE email=$1")
+        let user: Option<User> = sqlx::query_as(&format!("{USER_IN_DEFAULT_WORKSPACE} WHERE u.email = $1"))
             .bind(&input.email)
             .fetch_optional(&self.pool)
             .await?;
@@ -86,26 +139,53 @@ impl AppState {
     }
 
     pub async fn find_user_by_id(&self, id: u64) -> Result<Option<User>, AppError> {
-        let user = sqlx::query_as("SELECT * FROM users WHERE id=$1")
+        let user = sqlx::query_as(&format!("{USER_IN_DEFAULT_WORKSPACE} WHERE u.id = $1"))
             .bind(id as i64)
             .fetch_optional(&self.pool)
             .await?;
         Ok(user)
     }
 
+    /// the user in workspace `ws_id`, `None` unless they are a member of it
+    pub async fn find_member(&self, id: u64, ws_id: u64) -> Result<Option<User>, AppError> {
+        let user = sqlx::query_as(
+            r#"
+            SELECT u.id, m.ws_id, u.fullname, u.email, u.password_hash, m.role, u.created_at
+            FROM users u JOIN workspace_members m ON m.user_id = u.id
+            WHERE u.id = $1 AND m.ws_id = $2 AND m.deactivated_at IS NULL
+            "#,
+        )
+        .bind(id as i64)
+        .bind(ws_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(user)
+    }
+
     pub async fn fetch_chat_users(&self, ws_id: u64) -> Result<Vec<ChatUser>, AppError> {
-        let users = sqlx::query_as("SELECT * FROM users WHERE ws_id=$1")
-            .bind(ws_id as i64)
-            .fetch_all(&self.pool)
-            .await?;
+        let users = sqlx::query_as(
+            r#"
+            SELECT u.* FROM users u JOIN workspace_members m ON m.user_id = u.id
+            WHERE m.ws_id = $1 AND m.deactivated_at IS NULL
+            "#,
+        )
+        .bind(ws_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
         Ok(users)
     }
 
-    pub async fn fetch_chat_user_by_ids(&self, ids: &[i64]) -> Result<Vec<ChatUser>, AppError> {
-        let users = sqlx::query_as("SELECT * FROM users WHERE id=ANY($1)")
-            .bind(ids)
-            .fetch_all(&self.pool)
-            .await?;
+    pub async fn fetch_chat_user_by_ids(&self, ids: &[i64], ws_id: u64) -> Result<Vec<ChatUser>, AppError> {
+        let users = sqlx::query_as(
+            r#"
+            SELECT u.* FROM users u JOIN workspace_members m ON m.user_id = u.id
+            WHERE u.id = ANY($1) AND m.ws_id = $2 AND m.deactivated_at IS NULL
+            "#,
+        )
+        .bind(ids)
+        .bind(ws_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
         Ok(users)
     }
 }
@@ -148,7 +228,18 @@ fn verify_password(password: &str, password_hash: &str) -> Result<bool, AppError
 impl CreateUser {
     pub fn new(ws: &str, fullname: &str, email: &str, password: &str) -> Self {
         Self {
-            workspace: ws.to_string(),
+            workspace: Some(ws.to_string()),
+            invite: None,
+            fullname: fullname.to_string(),
+            email: email.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    pub fn with_invite(invite: &str, fullname: &str, email: &str, password: &str) -> Self {
+        Self {
+            workspace: None,
+            invite: Some(invite.to_string()),
             fullname: fullname.to_string(),
             email: email.to_string(),
             password: password.to_string(),
@@ -198,7 +289,7 @@ mod tests {
     #[tokio::test]
     async fn create_duplicate_user_should_fail() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
-        let input = CreateUser::new("none", "test user", "test@test.com", "password123");
+        let input = CreateUser::new("test", "test user", "test@test.com", "password123");
         let user = state.create_user(&input).await?;
         assert_eq!(user.email, "test@test.com");
         assert_eq!(user.fullname, "test user");
@@ -219,7 +310,7 @@ mod tests {
         let email = "test@test.com";
         let name = "test user";
         let password = "password123";
-        let input = CreateUser::new("none", name, email, password);
+        let input = CreateUser::new("test", name, email, password);
         let user = state.create_user(&input).await?;
         assert_eq!(user.email, email);
         assert_eq!(user.fullname, name);
@@ -235,4 +326,31 @@ mod tests {
         assert!(user.is_some());
         Ok(())
     }
+
+    #[tokio::test]
+    async fn create_user_should_create_workspace_only_explicitly() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state
+            .create_user(&CreateUser::new("new", "test", "test@test.com", "password123"))
+            .await?;
+        let ws = state
+            .find_workspace_by_name("new")
+            .await?
+            .expect("workspace should exist");
+        assert_eq!(user.ws_id, ws.id);
+        assert_eq!(ws.owner_id, user.id);
+        assert_eq!(user.role, Role::Owner);
+
+        // joining by name isn't possible
+        let input = CreateUser::new("acme", "eve", "eve@evil.org", "password123");
+        let err = state.create_user(&input).await.unwrap_err();
+        assert!(matches!(err, AppError::WorkspaceAlreadyExists(_)));
+        assert!(state.find_user_by_email("eve@evil.org").await?.is_none());
+
+        let mut input = CreateUser::with_invite("token", "eve", "eve@evil.org", "password123");
+        input.invite = None;
+        let err = state.create_user(&input).await.unwrap_err();
+        assert!(matches!(err, AppError::SignupError(_)));
+        Ok(())
+    }
 }
@@ -1,6 +1,69 @@
-use chat_core::{ChatUser, Workspace};
+use chat_core::{ChatUser, Permission, Role, User, Workspace};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use super::FileInfo;
+use crate::{error::AppError, storage::FileStore, AppState};
+
+// make $1 the owner of workspace $2, the previous owner stays an admin
+const TRANSFER_OWNERSHIP: &str = r#"
+    WITH prev AS (SELECT owner_id FROM workspaces WHERE id = $2),
+    roles AS (
+      UPDATE workspace_members
+      SET role = CASE WHEN user_id = $1 THEN 'owner' ELSE 'admin' END::workspace_role
+      WHERE ws_id = $2 AND (user_id = $1 OR user_id = (SELECT owner_id FROM prev))
+    )
+    UPDATE workspaces SET owner_id = $1 WHERE id = $2 RETURNING *
+"#;
+
+/// a workspace the user belongs to, as listed by the workspace switcher
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct UserWorkspace {
+    pub id: i64,
+    pub name: String,
+    pub owner_id: i64,
+    /// the user's role in it
+    pub role: Role,
+    pub joined_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct UpdateWorkspace {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// hand the workspace over to this member, only the owner may
+    #[serde(default)]
+    pub owner_id: Option<u64>,
+    /// channels of the workspace new members are added to
+    #[serde(default)]
+    pub default_channels: Option<Vec<u64>>,
+    /// days messages are kept, 0 keeps them forever
+    #[serde(default)]
+    pub retention_days: Option<u32>,
+}
+
+/// a member as listed to the workspace, deactivated ones included
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct WorkspaceMember {
+    pub id: i64,
+    pub fullname: String,
+    pub email: String,
+    pub role: Role,
+    pub joined_at: DateTime<Utc>,
+    /// when tokens were last issued to them for the workspace
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub deactivated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct JoinWorkspace {
+    /// token of an invite to the workspace
+    pub invite: String,
+}
 
-use crate::{error::AppError, AppState};
 #[allow(unused)]
 impl AppState {
     pub async fn create_workspace(&self, name: &str, user_id: u64) -> Result<Workspace, AppError> {
@@ -15,7 +78,7 @@ impl AppState {
 
     pub async fn update_workspace_owner(&self, id: u64, owner_id: u64) -> Result<Workspace, AppError> {
         // update owner_id in two cases 1) owner_id = 0 2) owner's ws_id = id
-        let ws = sqlx::query_as(r#"UPDATE workspaces SET owner_id = $1 WHERE id = $2 RETURNING *"#)
+        let ws = sqlx::query_as(TRANSFER_OWNERSHIP)
             .bind(owner_id as i64)
             .bind(id as i64)
             .fetch_one(&self.pool)
@@ -24,6 +87,270 @@ impl AppState {
         Ok(ws)
     }
 
+    /// rename the workspace, hand it over to another member or change its settings. handing
+    /// it over needs `Permission::TransferOwnership` of the caller's `role`.
+    pub async fn update_workspace(&self, id: u64, input: UpdateWorkspace, role: Role) -> Result<Workspace, AppError> {
+        if input.owner_id.is_some() && !role.can(Permission::TransferOwnership) {
+            return Err(AppError::PermissionDenied(format!(
+                "{role:?} may not transfer the workspace"
+            )));
+        }
+        let name = match &input.name {
+            Some(name) => {
+                let name = valid_workspace_name(name).ok_or_else(|| {
+                    AppError::UpdateWorkspaceError("workspace name must be 1 to 32 characters".to_string())
+                })?;
+                if self
+                    .find_workspace_by_name(name)
+                    .await?
+                    .is_some_and(|ws| ws.id != id as i64)
+                {
+                    return Err(AppError::WorkspaceAlreadyExists(name.to_string()));
+                }
+                Some(name)
+            }
+            None => None,
+        };
+        let retention_days = input
+            .retention_days
+            .map(i32::try_from)
+            .transpose()
+            .map_err(|_| AppError::UpdateWorkspaceError("retention_days is too large".to_string()))?;
+
+        let mut tx = self.pool.begin().await?;
+        let channels = match input.default_channels {
+            Some(channels) => {
+                let mut ids: Vec<i64> = channels.into_iter().map(|v| v as i64).collect();
+                ids.sort_unstable();
+                ids.dedup();
+                let found: i64 = sqlx::query_scalar(
+                    r#"
+                    SELECT count(*) FROM chats
+                    WHERE id = ANY($1) AND ws_id = $2 AND type IN ('public_channel', 'private_channel')
+                    "#,
+                )
+                .bind(&ids)
+                .bind(id as i64)
+                .fetch_one(&mut *tx)
+                .await?;
+                if found != ids.len() as i64 {
+                    return Err(AppError::UpdateWorkspaceError(
+                        "default channels must be channels of the workspace".to_string(),
+                    ));
+                }
+                Some(ids)
+            }
+            None => None,
+        };
+        if let Some(owner_id) = input.owner_id {
+            if !self.is_active_member(id, owner_id, &mut tx).await? {
+                return Err(AppError::UpdateWorkspaceError(format!(
+                    "user {owner_id} isn't a member of the workspace"
+                )));
+            }
+            sqlx::query(TRANSFER_OWNERSHIP)
+                .bind(owner_id as i64)
+                .bind(id as i64)
+                .execute(&mut *tx)
+                .await?;
+        }
+        let ws: Option<Workspace> = sqlx::query_as(
+            r#"
+            UPDATE workspaces
+            SET name = coalesce($2, name),
+                default_channels = coalesce($3, default_channels),
+                retention_days = coalesce($4, retention_days)
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+        .bind(id as i64)
+        .bind(name)
+        .bind(channels)
+        .bind(retention_days)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let ws = ws.ok_or_else(|| AppError::NotFound(format!("workspace id {id} not found")))?;
+        tx.commit().await?;
+        Ok(ws)
+    }
+
+    /// remove the workspace with its chats, messages and files. members stay users, they
+    /// sign in to another of their workspaces from now on. refused while an active member has
+    /// no other workspace, they couldn't sign in anywhere.
+    pub async fn delete_workspace(&self, id: u64) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        let stranded: Vec<String> = sqlx::query_scalar(
+            r#"
+            SELECT u.email FROM workspace_members m JOIN users u ON u.id = m.user_id
+            WHERE m.ws_id = $1 AND m.deactivated_at IS NULL AND NOT EXISTS (
+              SELECT 1 FROM workspace_members o
+              WHERE o.user_id = m.user_id AND o.ws_id <> $1 AND o.deactivated_at IS NULL
+            )
+            ORDER BY u.id
+            "#,
+        )
+        .bind(id as i64)
+        .fetch_all(&mut *tx)
+        .await?;
+        if !stranded.is_empty() {
+            return Err(AppError::UpdateWorkspaceError(format!(
+                "{} would have no workspace left",
+                stranded.join(", ")
+            )));
+        }
+        let files: Vec<FileInfo> = sqlx::query_as("DELETE FROM files WHERE ws_id = $1 RETURNING *")
+            .bind(id as i64)
+            .fetch_all(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+            UPDATE users u SET ws_id = coalesce((
+              SELECT m.ws_id FROM workspace_members m
+              WHERE m.user_id = u.id AND m.ws_id <> $1 AND m.deactivated_at IS NULL
+              ORDER BY m.joined_at LIMIT 1
+            ), 0)
+            WHERE u.ws_id = $1
+            "#,
+        )
+        .bind(id as i64)
+        .execute(&mut *tx)
+        .await?;
+        let ret = sqlx::query("DELETE FROM workspaces WHERE id = $1")
+            .bind(id as i64)
+            .execute(&mut *tx)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("workspace id {id} not found")));
+        }
+        tx.commit().await?;
+
+        // the keys are per workspace, nothing is shared with other ones
+        for info in files {
+            let file = info.chat_file();
+            let keys = self
+                .config
+                .server
+                .upload
+                .thumbnail_sizes
+                .iter()
+                .map(|&size| file.thumbnail_key(size));
+            for key in std::iter::once(file.key()).chain(keys) {
+                if let Err(e) = self.store.delete(&key).await {
+                    warn!("Failed to remove {}: {}", key, e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// members of the workspace with their roles, deactivated ones last
+    pub async fn list_members(&self, ws_id: u64) -> Result<Vec<WorkspaceMember>, AppError> {
+        let members = sqlx::query_as(
+            r#"
+            SELECT u.id, u.fullname, u.email, m.role, m.joined_at, m.last_seen_at, m.deactivated_at
+            FROM workspace_members m JOIN users u ON u.id = m.user_id
+            WHERE m.ws_id = $1
+            ORDER BY m.deactivated_at IS NOT NULL, u.id
+            "#,
+        )
+        .bind(ws_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(members)
+    }
+
+    /// keep the member out of the workspace: their tokens for it stop working and they are
+    /// hidden from its users. the owner can't be deactivated.
+    pub async fn deactivate_member(&self, ws_id: u64, user_id: u64) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        let role: Option<Role> = sqlx::query_scalar(
+            r#"
+            UPDATE workspace_members SET deactivated_at = now()
+            WHERE ws_id = $1 AND user_id = $2 AND deactivated_at IS NULL
+            RETURNING role
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&mut *tx)
+        .await?;
+        match role {
+            None => return Err(AppError::NotFound(format!("member id {user_id} not found"))),
+            Some(Role::Owner) => {
+                return Err(AppError::PermissionDenied(
+                    "the owner can't be deactivated, transfer the workspace first".to_string(),
+                ))
+            }
+            Some(_) => {}
+        }
+        // access tokens are refused by `TokenVerify` once the member is deactivated
+        sqlx::query(
+            "UPDATE refresh_tokens SET revoked_at = now() WHERE user_id = $1 AND ws_id = $2 AND revoked_at IS NULL",
+        )
+        .bind(user_id as i64)
+        .bind(ws_id as i64)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn reactivate_member(&self, ws_id: u64, user_id: u64) -> Result<(), AppError> {
+        let ret = sqlx::query(
+            r#"
+            UPDATE workspace_members SET deactivated_at = NULL
+            WHERE ws_id = $1 AND user_id = $2 AND deactivated_at IS NOT NULL
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .execute(&self.pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("deactivated member id {user_id} not found")));
+        }
+        Ok(())
+    }
+
+    /// add a new member to the default channels of the workspace
+    pub(super) async fn join_default_channels(
+        &self,
+        ws_id: i64,
+        user_id: i64,
+        conn: &mut PgConnection,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            UPDATE chats c SET members = array_append(c.members, $2)
+            FROM workspaces w
+            WHERE w.id = $1 AND c.ws_id = w.id AND c.id = ANY(w.default_channels)
+              AND NOT $2 = ANY(c.members)
+            "#,
+        )
+        .bind(ws_id)
+        .bind(user_id)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    async fn is_active_member(&self, ws_id: u64, user_id: u64, conn: &mut PgConnection) -> Result<bool, AppError> {
+        let active = sqlx::query_scalar(
+            r#"
+            SELECT EXISTS(
+              SELECT 1 FROM workspace_members
+              WHERE ws_id = $1 AND user_id = $2 AND deactivated_at IS NULL
+            )
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .fetch_one(conn)
+        .await?;
+        Ok(active)
+    }
+
     pub async fn find_workspace_by_name(&self, name: &str) -> Result<Option<Workspace>, AppError> {
         let ws = sqlx::query_as(r#"SELECT * FROM workspaces WHERE name = $1"#)
             .bind(name)
@@ -40,12 +367,42 @@ impl AppState {
         Ok(ws)
     }
 
+    /// the workspaces the user is a member of
+    pub async fn list_user_workspaces(&self, user_id: u64) -> Result<Vec<UserWorkspace>, AppError> {
+        let workspaces = sqlx::query_as(
+            r#"
+            SELECT w.id, w.name, w.owner_id, m.role, m.joined_at
+            FROM workspace_members m JOIN workspaces w ON w.id = m.ws_id
+            WHERE m.user_id = $1 AND m.deactivated_at IS NULL
+            ORDER BY m.joined_at, w.id
+            "#,
+        )
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(workspaces)
+    }
+
+    /// the user in workspace `ws_id`, which they sign in to from now on
+    pub async fn switch_workspace(&self, user_id: u64, ws_id: u64) -> Result<User, AppError> {
+        let user = self
+            .find_member(user_id, ws_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("workspace id {ws_id} not found")))?;
+        sqlx::query("UPDATE users SET ws_id = $2 WHERE id = $1")
+            .bind(user_id as i64)
+            .bind(ws_id as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(user)
+    }
+
     pub async fn fetch_workspace_all_chat_users(&self, id: u64) -> Result<Vec<ChatUser>, AppError> {
         let users = sqlx::query_as(
             r#"
-        SELECT id, fullname, email
-        FROM users
-        WHERE ws_id = $1 order by id
+        SELECT u.id, u.fullname, u.email
+        FROM users u JOIN workspace_members m ON m.user_id = u.id
+        WHERE m.ws_id = $1 AND m.deactivated_at IS NULL order by u.id
 
             "#,
         )
@@ -56,25 +413,31 @@ impl AppState {
     }
 }
 
+/// the name trimmed, `None` unless it's 1 to 32 characters
+pub(super) fn valid_workspace_name(name: &str) -> Option<&str> {
+    let name = name.trim();
+    (!name.is_empty() && name.chars().count() <= 32).then_some(name)
+}
+
 #[cfg(test)]
 mod tests {
 
     use anyhow::Result;
+    use bytes::Bytes;
 
     use super::*;
-    use crate::models::CreateUser;
+    use crate::{
+        models::{CreateInvite, CreateUser},
+        ChatFile,
+    };
     #[tokio::test]
     async fn workspace_should_create_and_set_owner() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
         let ws = state.create_workspace("test", 0).await?;
-        let input = CreateUser::new(&ws.name, "test", "123456@qq.com", "password1233");
-        let user = state.create_user(&input).await?;
-        let ws = state
-            .update_workspace_owner(ws.id as _, user.id as _)
-            .await?;
-        assert_eq!(user.ws_id, ws.id);
         assert_eq!(ws.name, "test");
-
+        assert_eq!(ws.owner_id, 0);
+        let input = CreateUser::new("other", "test", "123456@qq.com", "password1233");
+        let user = state.create_user(&input).await?;
         let ws = state
             .update_workspace_owner(ws.id as _, user.id as _)
             .await?;
@@ -100,4 +463,213 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn user_should_switch_between_workspaces() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // frank of foo is invited to acme
+        let token = state
+            .create_invite(CreateInvite::new(None), 1, 1)
+            .await?
+            .token
+            .unwrap();
+        let user = state.join_workspace(&token, 6, "frank@foo.org").await?;
+        assert_eq!((user.ws_id, user.role), (1, Role::Member));
+        // joining again doesn't use up the invite
+        state.join_workspace(&token, 6, "frank@foo.org").await?;
+        assert_eq!(state.list_invites(1).await?[0].uses, 1);
+
+        let workspaces = state.list_user_workspaces(6).await?;
+        let names: Vec<_> = workspaces
+            .iter()
+            .map(|w| (w.name.as_str(), w.role))
+            .collect();
+        assert_eq!(names, vec![("foo", Role::Owner), ("acme", Role::Member)]);
+        assert_eq!(state.fetch_workspace_all_chat_users(1).await?.len(), 6);
+        assert_eq!(state.fetch_workspace_all_chat_users(2).await?.len(), 2);
+
+        // the workspace switched to is the default one from now on
+        let user = state.switch_workspace(6, 2).await?;
+        assert_eq!((user.ws_id, user.role), (2, Role::Owner));
+        assert_eq!(state.find_user_by_id(6).await?.unwrap().ws_id, 2);
+        let user = state.switch_workspace(6, 1).await?;
+        assert_eq!(state.find_user_by_id(6).await?.unwrap(), user);
+
+        let err = state.switch_workspace(6, 3).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+
+        // refresh tokens stay in the workspace they were issued for
+        let refresh_token = state.create_refresh_token(6, 2).await?;
+        let (user, _) = state.rotate_refresh_token(&refresh_token).await?;
+        assert_eq!(user.ws_id, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_workspace_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = UpdateWorkspace {
+            name: Some(" acme corp ".to_string()),
+            default_channels: Some(vec![1, 2, 1]),
+            retention_days: Some(30),
+            ..Default::default()
+        };
+        let ws = state.update_workspace(1, input, Role::Owner).await?;
+        assert_eq!(ws.name, "acme corp");
+        assert_eq!(ws.default_channels, vec![1, 2]);
+        assert_eq!(ws.retention_days, 30);
+
+        let input = UpdateWorkspace {
+            name: Some("foo".to_string()),
+            ..Default::default()
+        };
+        let err = state
+            .update_workspace(1, input, Role::Owner)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::WorkspaceAlreadyExists(_)));
+        // only channels of the workspace
+        for channels in [vec![3], vec![5]] {
+            let input = UpdateWorkspace {
+                default_channels: Some(channels),
+                ..Default::default()
+            };
+            let err = state
+                .update_workspace(1, input, Role::Owner)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, AppError::UpdateWorkspaceError(_)));
+        }
+
+        // the owner hands it over and stays an admin
+        let input = UpdateWorkspace {
+            owner_id: Some(6),
+            ..Default::default()
+        };
+        let err = state
+            .update_workspace(1, input, Role::Owner)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::UpdateWorkspaceError(_)));
+        let input = UpdateWorkspace {
+            owner_id: Some(2),
+            ..Default::default()
+        };
+        let err = state
+            .update_workspace(1, input.clone(), Role::Admin)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+        let ws = state.update_workspace(1, input, Role::Owner).await?;
+        assert_eq!(ws.owner_id, 2);
+        assert_eq!(ws.name, "acme corp");
+        assert_eq!(state.find_member(2, 1).await?.unwrap().role, Role::Owner);
+        assert_eq!(state.find_member(1, 1).await?.unwrap().role, Role::Admin);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn new_members_should_join_default_channels() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = UpdateWorkspace {
+            default_channels: Some(vec![1, 2]),
+            ..Default::default()
+        };
+        state.update_workspace(1, input, Role::Owner).await?;
+        let token = state
+            .create_invite(CreateInvite::new(None), 1, 1)
+            .await?
+            .token
+            .unwrap();
+        let user = state
+            .create_user(&CreateUser::with_invite(&token, "ivy", "ivy@acme.org", "123456"))
+            .await?;
+        state.join_workspace(&token, 7, "grace@foo.org").await?;
+        for id in [1, 2] {
+            let chat = state.get_chat_by_id(id, 1).await?.unwrap();
+            assert!(chat.members.ends_with(&[user.id, 7]));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn deactivated_member_should_be_hidden() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let refresh_token = state.create_refresh_token(2, 1).await?;
+        state.deactivate_member(1, 2).await?;
+        let err = state.deactivate_member(1, 2).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        let err = state.deactivate_member(1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        let err = state
+            .rotate_refresh_token(&refresh_token)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::Unauthorized(_)));
+        let users = state.fetch_workspace_all_chat_users(1).await?;
+        assert!(users.iter().all(|u| u.id != 2));
+        assert!(state.find_user_by_id(2).await?.is_none());
+        let members = state.list_members(1).await?;
+        assert_eq!(members.len(), 5);
+        assert_eq!(members[4].id, 2);
+        assert!(members[4].deactivated_at.is_some());
+        assert!(members[0].last_seen_at.is_none());
+
+        state.reactivate_member(1, 2).await?;
+        assert_eq!(state.fetch_workspace_all_chat_users(1).await?.len(), 5);
+        state.create_refresh_token(2, 1).await?;
+        let members = state.list_members(1).await?;
+        assert!(members[1].last_seen_at.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_workspace_should_remove_everything() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // frank of foo is a member of acme too, acme is his default
+        let token = state
+            .create_invite(CreateInvite::new(None), 1, 1)
+            .await?
+            .token
+            .unwrap();
+        state.join_workspace(&token, 6, "frank@foo.org").await?;
+        // the others of acme have nowhere else to go yet
+        let err = state.delete_workspace(1).await.unwrap_err();
+        assert!(matches!(err, AppError::UpdateWorkspaceError(_)));
+        assert!(state.find_workspace_by_id(1).await?.is_some());
+        let token = state
+            .create_invite(CreateInvite::new(None), 2, 6)
+            .await?
+            .token
+            .unwrap();
+        for user_id in 1..=5 {
+            let user = state.find_user_by_id(user_id).await?.unwrap();
+            state
+                .join_workspace(&token, user_id as _, &user.email)
+                .await?;
+        }
+        let data = format!("delete me {}", uuid::Uuid::now_v7());
+        let file = ChatFile::new(1, "a.txt", data.as_bytes());
+        state
+            .create_file(&file, Bytes::from(data), "a.txt", "text/plain", None, 1)
+            .await?;
+
+        state.delete_workspace(1).await?;
+        assert!(state.find_workspace_by_id(1).await?.is_none());
+        assert!(state.get_chat_by_id(1, 1).await?.is_none());
+        assert_eq!(state.store.head(&file.key()).await?, None);
+        let messages: i64 = sqlx::query_scalar("SELECT count(*) FROM messages WHERE chat_id < 5")
+            .fetch_one(&state.pool)
+            .await?;
+        assert_eq!(messages, 0);
+        // the members sign in to their other workspace from now on
+        assert_eq!(state.find_user_by_id(6).await?.unwrap().ws_id, 2);
+        assert_eq!(state.find_user_by_id(1).await?.unwrap().ws_id, 2);
+        assert_eq!(state.fetch_workspace_all_chat_users(2).await?.len(), 7);
+
+        let err = state.delete_workspace(1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
 }
@@ -1,5 +1,5 @@
 use axum::Router;
-use chat_core::{Chat, ChatType, ChatUser, Message, User, Workspace};
+use chat_core::{Chat, ChatRead, ChatType, ChatUser, Jwk, Jwks, Message, MessageFile, Reaction, Role, User, Workspace};
 use utoipa::{
     openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
     Modify, OpenApi,
@@ -9,7 +9,11 @@ use utoipa_redoc::{Redoc, Servable};
 use utoipa_swagger_ui::SwaggerUi;
 
 use crate::handlers::*;
-use crate::{AppState, CreateChat, CreateMessage, CreateUser, ErrorOutput, ListMessages, SigninUser};
+use crate::{
+    AddChatMembers, AppState, CollectFiles, CreateChat, CreateInvite, CreateMessage, CreateUser, ErrorOutput,
+    FileGcReport, FileInfo, Invite, JoinWorkspace, ListMessages, MarkRead, PublicChannel, RefreshInput, SearchHit,
+    SearchMessages, SigninUser, UpdateChat, UpdateMessage, UpdateWorkspace, UserWorkspace, WorkspaceMember,
+};
 
 pub(crate) trait OpenApiRouter {
     fn openapi(self) -> Self;
@@ -20,13 +24,44 @@ pub(crate) trait OpenApiRouter {
         paths(
             signup_handler,
             signin_handler,
+            refresh_handler,
+            signout_handler,
+            jwks_handler,
             list_chat_handler,
             create_chat_handler,
             get_chat_handler,
+            update_chat_handler,
+            delete_chat_handler,
+            add_chat_members_handler,
+            remove_chat_member_handler,
+            list_channels_handler,
+            join_chat_handler,
+            leave_chat_handler,
+            mark_read_handler,
             list_message_handler,
+            list_replies_handler,
+            update_message_handler,
+            delete_message_handler,
+            add_reaction_handler,
+            remove_reaction_handler,
+            search_handler,
+            file_meta_handler,
+            collect_files_handler,
+            create_invite_handler,
+            list_invites_handler,
+            revoke_invite_handler,
+            list_workspaces_handler,
+            switch_workspace_handler,
+            join_workspace_handler,
+            get_workspace_handler,
+            update_workspace_handler,
+            delete_workspace_handler,
+            list_members_handler,
+            deactivate_member_handler,
+            reactivate_member_handler,
         ),
         components(
-            schemas(User, Chat, ChatType, ChatUser, Message, Workspace, SigninUser, CreateUser, CreateChat, CreateMessage, ListMessages, AuthOutput, ErrorOutput),
+            schemas(User, Role, Chat, PublicChannel, ChatRead, ChatType, ChatUser, Message, MessageFile, Reaction, FileInfo, CollectFiles, FileGcReport, Invite, CreateInvite, Workspace, UserWorkspace, JoinWorkspace, UpdateWorkspace, WorkspaceMember, SigninUser, CreateUser, RefreshInput, CreateChat, UpdateChat, AddChatMembers, CreateMessage, UpdateMessage, ListMessages, MarkRead, SearchMessages, SearchHit, AuthOutput, Jwks, Jwk, ErrorOutput),
         ),
         modifiers(&SecurityAddon),
         tags(
@@ -0,0 +1,152 @@
+use std::{
+    io::{ErrorKind, SeekFrom},
+    path::{Component, Path, PathBuf},
+};
+
+use bytes::Bytes;
+use futures::StreamExt;
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+use tokio_util::io::ReaderStream;
+
+use super::{ByteStream, FileMeta, FileStore};
+use crate::error::AppError;
+
+/// files under `base_dir`, the key is the relative path
+pub struct LocalStore {
+    base_dir: PathBuf,
+}
+
+impl LocalStore {
+    pub fn new(base_dir: impl AsRef<Path>) -> Self {
+        Self {
+            base_dir: base_dir.as_ref().to_path_buf(),
+        }
+    }
+
+    // keys come from request paths, they must not escape base_dir
+    fn path(&self, key: &str) -> Result<PathBuf, AppError> {
+        let key = Path::new(key);
+        if key.components().any(|c| !matches!(c, Component::Normal(_))) {
+            return Err(AppError::NotFound(format!("file {} not found", key.display())));
+        }
+        Ok(self.base_dir.join(key))
+    }
+}
+
+impl FileStore for LocalStore {
+    async fn put(&self, key: &str, data: Bytes) -> Result<(), AppError> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(path, data).await?;
+        Ok(())
+    }
+
+    async fn put_file(&self, key: &str, src: &Path) -> Result<(), AppError> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        // a rename can't cross file systems
+        if fs::rename(src, &path).await.is_err() {
+            fs::copy(src, &path).await?;
+            fs::remove_file(src).await?;
+        }
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Bytes, AppError> {
+        match fs::read(self.path(key)?).await {
+            Ok(data) => Ok(data.into()),
+            Err(e) => Err(not_found(key, e)),
+        }
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<FileMeta>, AppError> {
+        match fs::metadata(self.path(key)?).await {
+            Ok(meta) if meta.is_file() => Ok(Some(FileMeta { size: meta.len() })),
+            Ok(_) => Ok(None),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), AppError> {
+        match fs::remove_file(self.path(key)?).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn stream(&self, key: &str, range: Option<(u64, u64)>) -> Result<ByteStream, AppError> {
+        let mut file = match fs::File::open(self.path(key)?).await {
+            Ok(file) => file,
+            Err(e) => return Err(not_found(key, e)),
+        };
+        match range {
+            Some((start, end)) => {
+                file.seek(SeekFrom::Start(start)).await?;
+                Ok(ReaderStream::new(file.take(end - start + 1)).boxed())
+            }
+            None => Ok(ReaderStream::new(file).boxed()),
+        }
+    }
+}
+
+fn not_found(key: &str, e: std::io::Error) -> AppError {
+    match e.kind() {
+        ErrorKind::NotFound => AppError::NotFound(format!("file {key} not found")),
+        _ => e.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use futures::TryStreamExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn local_store_should_work() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("chat_store_{}", uuid::Uuid::now_v7()));
+        let store = LocalStore::new(&dir);
+        store.put("1/abc/def.txt", Bytes::from("hello")).await?;
+        assert_eq!(store.get("1/abc/def.txt").await?, Bytes::from("hello"));
+        assert_eq!(store.head("1/abc/def.txt").await?, Some(FileMeta { size: 5 }));
+        let chunks: Vec<Bytes> = store
+            .stream("1/abc/def.txt", None)
+            .await?
+            .try_collect()
+            .await?;
+        assert_eq!(chunks.concat(), b"hello");
+        let chunks: Vec<Bytes> = store
+            .stream("1/abc/def.txt", Some((1, 3)))
+            .await?
+            .try_collect()
+            .await?;
+        assert_eq!(chunks.concat(), b"ell");
+
+        store.delete("1/abc/def.txt").await?;
+        store.delete("1/abc/def.txt").await?;
+        assert_eq!(store.head("1/abc/def.txt").await?, None);
+        assert!(matches!(store.get("1/abc/def.txt").await, Err(AppError::NotFound(_))));
+
+        // a local file is moved in
+        let src = dir.join("upload");
+        std::fs::write(&src, "world")?;
+        store.put_file("1/abc/ghi.txt", &src).await?;
+        assert_eq!(store.get("1/abc/ghi.txt").await?, Bytes::from("world"));
+        assert!(!src.exists());
+
+        // no way out of base_dir
+        assert!(store.get("1/../../etc/passwd").await.is_err());
+        assert!(store.put("/tmp/x", Bytes::new()).await.is_err());
+        let _ = std::fs::remove_dir_all(dir);
+        Ok(())
+    }
+}
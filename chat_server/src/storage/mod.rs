@@ -0,0 +1,130 @@
+mod local;
+mod s3;
+
+use std::{future::Future, path::Path};
+
+use bytes::Bytes;
+use futures::stream::BoxStream;
+pub use local::LocalStore;
+pub use s3::S3Store;
+
+use crate::{
+    config::{ServerConfig, StorageConfig},
+    error::AppError,
+};
+
+pub type ByteStream = BoxStream<'static, Result<Bytes, std::io::Error>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileMeta {
+    pub size: u64,
+}
+
+/// where uploaded files live. keys are `ChatFile::key`, e.g. `1/2aa/e6c/35c9...ed.txt`.
+pub trait FileStore {
+    /// store `data` under `key`, overwriting whatever was there
+    fn put(&self, key: &str, data: Bytes) -> impl Future<Output = Result<(), AppError>> + Send;
+
+    /// move the local file at `path` to `key`, overwriting whatever was there
+    fn put_file(&self, key: &str, path: &Path) -> impl Future<Output = Result<(), AppError>> + Send;
+
+    /// the whole file, `AppError::NotFound` if there's none
+    fn get(&self, key: &str) -> impl Future<Output = Result<Bytes, AppError>> + Send;
+
+    /// metadata of the file, `None` if there's none
+    fn head(&self, key: &str) -> impl Future<Output = Result<Option<FileMeta>, AppError>> + Send;
+
+    /// remove the file, removing a missing file is fine
+    fn delete(&self, key: &str) -> impl Future<Output = Result<(), AppError>> + Send;
+
+    /// the file, or the inclusive byte `range` of it, as a stream of chunks.
+    /// `AppError::NotFound` if there's none.
+    fn stream(&self, key: &str, range: Option<(u64, u64)>)
+        -> impl Future<Output = Result<ByteStream, AppError>> + Send;
+}
+
+/// the content of an upload, kept in memory or received into a local file
+pub enum FileContent<'a> {
+    Bytes(Bytes),
+    Path(&'a Path),
+}
+
+impl FileContent<'_> {
+    pub async fn size(&self) -> Result<u64, AppError> {
+        match self {
+            Self::Bytes(data) => Ok(data.len() as _),
+            Self::Path(path) => Ok(tokio::fs::metadata(path).await?.len()),
+        }
+    }
+}
+
+impl From<Bytes> for FileContent<'_> {
+    fn from(data: Bytes) -> Self {
+        Self::Bytes(data)
+    }
+}
+
+impl<'a> From<&'a Path> for FileContent<'a> {
+    fn from(path: &'a Path) -> Self {
+        Self::Path(path)
+    }
+}
+
+/// the store selected by `server.storage` in chat.yaml
+pub enum Storage {
+    Local(LocalStore),
+    S3(S3Store),
+}
+
+impl Storage {
+    pub fn new(config: &ServerConfig) -> Result<Self, AppError> {
+        match &config.storage {
+            StorageConfig::Local => Ok(Self::Local(LocalStore::new(&config.base_dir))),
+            StorageConfig::S3(s3) => Ok(Self::S3(S3Store::new(s3)?)),
+        }
+    }
+}
+
+impl FileStore for Storage {
+    async fn put(&self, key: &str, data: Bytes) -> Result<(), AppError> {
+        match self {
+            Self::Local(store) => store.put(key, data).await,
+            Self::S3(store) => store.put(key, data).await,
+        }
+    }
+
+    async fn put_file(&self, key: &str, path: &Path) -> Result<(), AppError> {
+        match self {
+            Self::Local(store) => store.put_file(key, path).await,
+            Self::S3(store) => store.put_file(key, path).await,
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Bytes, AppError> {
+        match self {
+            Self::Local(store) => store.get(key).await,
+            Self::S3(store) => store.get(key).await,
+        }
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<FileMeta>, AppError> {
+        match self {
+            Self::Local(store) => store.head(key).await,
+            Self::S3(store) => store.head(key).await,
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), AppError> {
+        match self {
+            Self::Local(store) => store.delete(key).await,
+            Self::S3(store) => store.delete(key).await,
+        }
+    }
+
+    async fn stream(&self, key: &str, range: Option<(u64, u64)>) -> Result<ByteStream, AppError> {
+        match self {
+            Self::Local(store) => store.stream(key, range).await,
+            Self::S3(store) => store.stream(key, range).await,
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use std::{path::Path, time::Duration};
+
+use bytes::Bytes;
+use futures::{StreamExt, TryStreamExt};
+use reqwest::{
+    header::{CONTENT_LENGTH, RANGE},
+    Body, Client, Response, StatusCode, Url,
+};
+use rusty_s3::{Bucket, Credentials, S3Action, UrlStyle};
+use tokio::fs::{self, File};
+use tokio_util::io::ReaderStream;
+
+use super::{ByteStream, FileMeta, FileStore};
+use crate::{config::S3Config, error::AppError};
+
+// requests are sent right away, the signature only has to outlive them
+const SIGNATURE_TTL: Duration = Duration::from_secs(60);
+
+/// an S3-compatible bucket (AWS, MinIO, ...), the key is the object name
+pub struct S3Store {
+    bucket: Bucket,
+    credentials: Credentials,
+    client: Client,
+}
+
+impl S3Store {
+    pub fn new(config: &S3Config) -> Result<Self, AppError> {
+        let endpoint: Url = config
+            .endpoint
+            .parse()
+            .map_err(|e| AppError::StorageError(format!("invalid s3 endpoint {}: {e}", config.endpoint)))?;
+        let style = if config.path_style {
+            UrlStyle::Path
+        } else {
+            UrlStyle::VirtualHost
+        };
+        let bucket = Bucket::new(endpoint, style, config.bucket.clone(), config.region.clone())
+            .map_err(|e| AppError::StorageError(format!("invalid s3 bucket: {e:?}")))?;
+        Ok(Self {
+            bucket,
+            credentials: Credentials::new(&config.access_key, &config.secret_key),
+            client: Client::new(),
+        })
+    }
+
+    async fn send_get(&self, key: &str, range: Option<(u64, u64)>) -> Result<Response, AppError> {
+        let url = self
+            .bucket
+            .get_object(Some(&self.credentials), key)
+            .sign(SIGNATURE_TTL);
+        let mut req = self.client.get(url);
+        if let Some((start, end)) = range {
+            req = req.header(RANGE, format!("bytes={start}-{end}"));
+        }
+        let res = req.send().await.map_err(storage_error)?;
+        match res.status() {
+            StatusCode::NOT_FOUND => Err(AppError::NotFound(format!("file {key} not found"))),
+            _ => res.error_for_status().map_err(storage_error),
+        }
+    }
+}
+
+impl FileStore for S3Store {
+    async fn put(&self, key: &str, data: Bytes) -> Result<(), AppError> {
+        let url = self
+            .bucket
+            .put_object(Some(&self.credentials), key)
+            .sign(SIGNATURE_TTL);
+        self.client
+            .put(url)
+            .body(data)
+            .send()
+            .await
+            .and_then(Response::error_for_status)
+            .map_err(storage_error)?;
+        Ok(())
+    }
+
+    async fn put_file(&self, key: &str, path: &Path) -> Result<(), AppError> {
+        let file = File::open(path).await?;
+        let size = file.metadata().await?.len();
+        let url = self
+            .bucket
+            .put_object(Some(&self.credentials), key)
+            .sign(SIGNATURE_TTL);
+        self.client
+            .put(url)
+            .header(CONTENT_LENGTH, size)
+            .body(Body::wrap_stream(ReaderStream::new(file)))
+            .send()
+            .await
+            .and_then(Response::error_for_status)
+            .map_err(storage_error)?;
+        fs::remove_file(path).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Bytes, AppError> {
+        self.send_get(key, None)
+            .await?
+            .bytes()
+            .await
+            .map_err(storage_error)
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<FileMeta>, AppError> {
+        let url = self
+            .bucket
+            .head_object(Some(&self.credentials), key)
+            .sign(SIGNATURE_TTL);
+        let res = self.client.head(url).send().await.map_err(storage_error)?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let res = res.error_for_status().map_err(storage_error)?;
+        // reqwest reports 0 for HEAD responses, the header has the real size
+        let size = res
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default();
+        Ok(Some(FileMeta { size }))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), AppError> {
+        let url = self
+            .bucket
+            .delete_object(Some(&self.credentials), key)
+            .sign(SIGNATURE_TTL);
+        let res = self
+            .client
+            .delete(url)
+            .send()
+            .await
+            .map_err(storage_error)?;
+        if res.status() != StatusCode::NOT_FOUND {
+            res.error_for_status().map_err(storage_error)?;
+        }
+        Ok(())
+    }
+
+    async fn stream(&self, key: &str, range: Option<(u64, u64)>) -> Result<ByteStream, AppError> {
+        let res = self.send_get(key, range).await?;
+        Ok(res.bytes_stream().map_err(std::io::Error::other).boxed())
+    }
+}
+
+fn storage_error(e: reqwest::Error) -> AppError {
+    AppError::StorageError(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use anyhow::Result;
+    use axum::{
+        body::Bytes as Body,
+        extract::{Path, State},
+        http::{header, StatusCode},
+        response::IntoResponse,
+        routing::get,
+        Router,
+    };
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    type Objects = Arc<Mutex<HashMap<String, Bytes>>>;
+
+    /// a minimal stand-in for MinIO: path-style objects kept in memory, signatures aren't checked
+    async fn serve_s3() -> Result<S3Config> {
+        async fn get_object(State(objects): State<Objects>, Path(key): Path<String>) -> impl IntoResponse {
+            match objects.lock().unwrap().get(&key) {
+                Some(data) => (StatusCode::OK, data.clone()).into_response(),
+                None => StatusCode::NOT_FOUND.into_response(),
+            }
+        }
+        async fn head_object(State(objects): State<Objects>, Path(key): Path<String>) -> impl IntoResponse {
+            match objects.lock().unwrap().get(&key) {
+                Some(data) => (StatusCode::OK, [(header::CONTENT_LENGTH, data.len().to_string())]).into_response(),
+                None => StatusCode::NOT_FOUND.into_response(),
+            }
+        }
+        async fn put_object(State(objects): State<Objects>, Path(key): Path<String>, body: Body) -> StatusCode {
+            objects.lock().unwrap().insert(key, body);
+            StatusCode::OK
+        }
+        async fn delete_object(State(objects): State<Objects>, Path(key): Path<String>) -> StatusCode {
+            objects.lock().unwrap().remove(&key);
+            StatusCode::NO_CONTENT
+        }
+
+        let app = Router::new()
+            .route(
+                "/chat/*key",
+                get(get_object)
+                    .head(head_object)
+                    .put(put_object)
+                    .delete(delete_object),
+            )
+            .with_state(Objects::default());
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Ok(S3Config {
+            endpoint: format!("http://{addr}"),
+            bucket: "chat".to_string(),
+            region: "us-east-1".to_string(),
+            access_key: "minioadmin".to_string(),
+            secret_key: "minioadmin".to_string(),
+            path_style: true,
+        })
+    }
+
+    #[tokio::test]
+    async fn s3_store_should_work() -> Result<()> {
+        let store = S3Store::new(&serve_s3().await?)?;
+        store.put("1/abc/def.txt", Bytes::from("hello")).await?;
+        assert_eq!(store.get("1/abc/def.txt").await?, Bytes::from("hello"));
+        assert_eq!(store.head("1/abc/def.txt").await?, Some(FileMeta { size: 5 }));
+        let chunks: Vec<Bytes> = store
+            .stream("1/abc/def.txt", None)
+            .await?
+            .try_collect()
+            .await?;
+        assert_eq!(chunks.concat(), b"hello");
+
+        let src = std::env::temp_dir().join(format!("chat_s3_{}", uuid::Uuid::now_v7()));
+        std::fs::write(&src, "world")?;
+        store.put_file("1/abc/ghi.txt", &src).await?;
+        assert_eq!(store.get("1/abc/ghi.txt").await?, Bytes::from("world"));
+        assert!(!src.exists());
+
+        store.delete("1/abc/def.txt").await?;
+        assert_eq!(store.head("1/abc/def.txt").await?, None);
+        assert!(matches!(store.get("1/abc/def.txt").await, Err(AppError::NotFound(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+use std::{io::Cursor, path::PathBuf, sync::Arc};
+
+use anyhow::Result;
+use bytes::Bytes;
+use image::{DynamicImage, ImageFormat, ImageReader};
+use tokio::{sync::mpsc, task};
+use tracing::{info, warn};
+
+use crate::{
+    storage::{FileStore, Storage},
+    ChatFile,
+};
+
+// uploads waiting for their thumbnails, more than that are skipped
+const QUEUE_SIZE: usize = 256;
+
+/// makes webp thumbnails of uploaded images in the background, they are kept next
+/// to the original under `ChatFile::thumbnail_key`
+pub struct Thumbnailer {
+    tx: mpsc::Sender<ChatFile>,
+}
+
+impl Thumbnailer {
+    /// start the worker, it stops once the `Thumbnailer` is dropped
+    pub fn new(store: Arc<Storage>, sizes: Vec<u32>) -> Self {
+        let (tx, mut rx) = mpsc::channel::<ChatFile>(QUEUE_SIZE);
+        tokio::spawn(async move {
+            while let Some(file) = rx.recv().await {
+                match make_thumbnails(store.as_ref(), &file, &sizes).await {
+                    Ok(()) => info!("Thumbnails of {} done", file.key()),
+                    Err(e) => warn!("Thumbnails of {} failed: {}", file.key(), e),
+                }
+            }
+        });
+        Self { tx }
+    }
+
+    /// queue an image which is in the store already
+    pub fn enqueue(&self, file: ChatFile) {
+        let key = file.key();
+        if self.tx.try_send(file).is_err() {
+            warn!("Thumbnail queue is full, skipping {}", key);
+        }
+    }
+}
+
+/// width and height of the image at `path`, `None` if it isn't one
+pub async fn image_dimensions(path: PathBuf) -> Option<(u32, u32)> {
+    task::spawn_blocking(move || {
+        ImageReader::open(path)
+            .ok()?
+            .with_guessed_format()
+            .ok()?
+            .into_dimensions()
+            .ok()
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+/// the thumbnails of `file` which aren't in the store yet. an image that fits
+/// into a size already gets none, the original is served for it.
+async fn make_thumbnails(store: &impl FileStore, file: &ChatFile, sizes: &[u32]) -> Result<()> {
+    let mut missing = vec![];
+    for &size in sizes {
+        if store.head(&file.thumbnail_key(size)).await?.is_none() {
+            missing.push(size);
+        }
+    }
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let data = store.get(&file.key()).await?;
+    let thumbnails = task::spawn_blocking(move || render(&data, &missing)).await??;
+    for (size, data) in thumbnails {
+        store.put(&file.thumbnail_key(size), data).await?;
+    }
+    Ok(())
+}
+
+fn render(data: &[u8], sizes: &[u32]) -> Result<Vec<(u32, Bytes)>> {
+    let image = image::load_from_memory(data)?;
+    let longest = image.width().max(image.height());
+    sizes
+        .iter()
+        .filter(|&&size| size < longest)
+        .map(|&size| {
+            // the webp encoder takes 8 bit rgb(a) only
+            let thumbnail = DynamicImage::ImageRgba8(image.thumbnail(size, size).to_rgba8());
+            let mut out = Cursor::new(vec![]);
+            thumbnail.write_to(&mut out, ImageFormat::WebP)?;
+            Ok((size, out.into_inner().into()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use super::*;
+    use crate::storage::LocalStore;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut out = Cursor::new(vec![]);
+        DynamicImage::ImageRgb8(RgbImage::new(width, height))
+            .write_to(&mut out, ImageFormat::Png)
+            .unwrap();
+        out.into_inner()
+    }
+
+    #[tokio::test]
+    async fn thumbnails_should_be_made() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("chat_thumbnail_{}", uuid::Uuid::now_v7()));
+        let store = LocalStore::new(&dir);
+        let data = png(600, 300);
+        let file = ChatFile::new(1, "a.png", &data);
+        store.put(&file.key(), data.into()).await?;
+
+        make_thumbnails(&store, &file, &[256, 1024]).await?;
+        let thumbnail = store.get(&file.thumbnail_key(256)).await?;
+        let thumbnail = image::load_from_memory_with_format(&thumbnail, ImageFormat::WebP)?;
+        assert_eq!((thumbnail.width(), thumbnail.height()), (256, 128));
+        // the original is small enough for this one
+        assert_eq!(store.head(&file.thumbnail_key(1024)).await?, None);
+
+        let path = dir.join(file.key());
+        assert_eq!(image_dimensions(path).await, Some((600, 300)));
+        assert_eq!(image_dimensions(dir.join("missing")).await, None);
+
+        // not an image
+        let file = ChatFile::new(1, "a.txt", b"hello");
+        store.put(&file.key(), Bytes::from("hello")).await?;
+        assert!(make_thumbnails(&store, &file, &[256]).await.is_err());
+        let _ = std::fs::remove_dir_all(dir);
+        Ok(())
+    }
+}
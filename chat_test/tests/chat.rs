@@ -49,7 +49,7 @@ async fn chat_server_should_work() -> Result<()> {
     let (tdb, state) = chat_server::AppState::new_for_test().await?;
     let chat_server = ChatServer::new(state).await?;
     let db_url = tdb.url();
-    NotifyServer::new(&db_url, &chat_server.token).await?;
+    NotifyServer::new(&db_url, &chat_server.token, chat_server.addr).await?;
     let chat = chat_server.create_chat().await?;
     let _msg = chat_server.create_message(chat.id as u64).await?;
     sleep(Duration::from_secs(10)).await;
@@ -57,9 +57,12 @@ async fn chat_server_should_work() -> Result<()> {
 }
 
 impl NotifyServer {
-    async fn new(db_url: &str, token: &str) -> Result<Self> {
+    async fn new(db_url: &str, token: &str, chat_addr: SocketAddr) -> Result<Self> {
         let mut config = notify_server::AppConfig::load()?;
         config.server.db_url = db_url.to_string();
+        // only trust the keys chat_server publishes
+        config.auth.keys.clear();
+        config.auth.jwks_url = Some(format!("http://{}/.well-known/jwks.json", chat_addr));
         let app = notify_server::get_router(config).await?;
         let listener = TcpListener::bind(WILD_ADDR).await?;
         let addr = listener.local_addr()?;
@@ -180,6 +183,90 @@ impl ChatServer {
         assert_eq!(res.status(), StatusCode::OK);
         let ret: Vec<String> = res.json().await?;
 
+        // denied types and malformed bodies are refused
+        let form = Form::new().part("file", Part::bytes(data.as_slice()).file_name("setup.exe"));
+        let res = self
+            .client
+            .post(format!("http://{}/api/upload", self.addr))
+            .bearer_auth(&self.token)
+            .multipart(form)
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let res = self
+            .client
+            .post(format!("http://{}/api/upload", self.addr))
+            .bearer_auth(&self.token)
+            .header("Content-Type", "multipart/form-data; boundary=x")
+            .body("--x\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\r\nab")
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        // download it whole, in parts and from cache
+        let url = format!("http://{}/api{}", self.addr, ret[0]);
+        let res = self
+            .client
+            .get(&url)
+            .bearer_auth(&self.token)
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let etag = res.headers()["etag"].clone();
+        assert!(res.headers()["cache-control"]
+            .to_str()?
+            .contains("immutable"));
+        assert!(res.headers()["content-disposition"]
+            .to_str()?
+            .starts_with("attachment; filename=\"Cargo.toml\""));
+        assert_eq!(res.headers()["x-content-type-options"], "nosniff");
+        assert_eq!(res.bytes().await?.as_ref(), data);
+        let res = self
+            .client
+            .get(&url)
+            .bearer_auth(&self.token)
+            .header("Range", "bytes=0-9")
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            res.headers()["content-range"],
+            format!("bytes 0-9/{}", data.len()).as_str()
+        );
+        assert_eq!(res.bytes().await?.as_ref(), &data[..10]);
+        let res = self
+            .client
+            .get(&url)
+            .bearer_auth(&self.token)
+            .header("If-None-Match", etag)
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        // but a file that isn't there is never
+        let res = self
+            .client
+            .get(format!(
+                "http://{}/api/files/1/000/000/0000000000000000000000000000000000.toml",
+                self.addr
+            ))
+            .bearer_auth(&self.token)
+            .header("If-None-Match", "*")
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        // no thumbnail of a text file, the original it is
+        let res = self
+            .client
+            .get(format!("{url}?size=256"))
+            .bearer_auth(&self.token)
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers()["cache-control"]
+            .to_str()?
+            .contains("no-cache"));
+        assert_eq!(res.bytes().await?.as_ref(), data);
+
         let body = serde_json::to_string(&json!({
             "content": "hello",
             "files": ret,
@@ -194,7 +281,11 @@ impl ChatServer {
         assert_eq!(res.status(), StatusCode::CREATED);
         let message: Message = res.json().await?;
         assert_eq!(message.content, "hello");
-        assert_eq!(message.files, ret);
+        let urls: Vec<_> = message.files.iter().map(|f| f.url.clone()).collect();
+        assert_eq!(urls, ret);
+        assert_eq!(message.files[0].name, "Cargo.toml");
+        assert_eq!(message.files[0].size, Some(data.len() as i64));
+        assert_eq!(message.files[0].mime.as_deref(), Some("text/plain"));
         assert_eq!(message.sender_id, 1);
         assert_eq!(message.chat_id, chat_id as i64);
         Ok(message)
@@ -0,0 +1,386 @@
+use std::net::SocketAddr;
+
+use anyhow::Result;
+use chat_core::{Chat, ChatUser};
+use chat_server::ChatFile;
+use reqwest::{
+    multipart::{Form, Part},
+    Method, StatusCode,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+
+/*
+every route is called by frank@foo.org (workspace foo) against resources of workspace acme,
+all of them should behave as if the resource doesn't exist.
+*/
+
+#[derive(Debug, Deserialize)]
+struct AuthToken {
+    token: String,
+}
+
+struct ChatServer {
+    addr: SocketAddr,
+    client: reqwest::Client,
+}
+
+const WILD_ADDR: &str = "localhost:0";
+
+#[tokio::test]
+async fn cross_workspace_access_should_404() -> Result<()> {
+    let (_tdb, state) = chat_server::AppState::new_for_test().await?;
+    let server = ChatServer::new(state.clone()).await?;
+    let acme = server.signin("tchen@acme.org").await?;
+    let foo = server.signin("frank@foo.org").await?;
+
+    // chat 1 belongs to acme
+    let routes = [
+        (Method::GET, "/api/chats/1", None),
+        (Method::PATCH, "/api/chats/1", Some(json!({ "name": "hacked" }))),
+        (Method::DELETE, "/api/chats/1", None),
+        (
+            Method::POST,
+            "/api/chats/1",
+            Some(json!({ "content": "hello", "files": [] })),
+        ),
+        (Method::GET, "/api/chats/1/messages?limit=10", None),
+        (
+            Method::PATCH,
+            "/api/chats/1/messages/1",
+            Some(json!({ "content": "hacked" })),
+        ),
+        (Method::DELETE, "/api/chats/1/messages/1", None),
+        (Method::GET, "/api/chats/1/messages/1/replies?limit=10", None),
+        (Method::POST, "/api/chats/1/messages/1/reactions/👍", None),
+        (Method::DELETE, "/api/chats/1/messages/1/reactions/👍", None),
+        (Method::POST, "/api/chats/1/read", Some(json!({ "message_id": 1 }))),
+        (Method::POST, "/api/chats/1/members", Some(json!({ "members": [6] }))),
+        (Method::DELETE, "/api/chats/1/members/1", None),
+    ];
+    for (method, path, body) in routes {
+        let status = server.call(&foo, method.clone(), path, body).await?;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{method} {path}");
+    }
+
+    // the chat is untouched
+    let status = server
+        .call(&acme, Method::GET, "/api/chats/1", None)
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+
+    // listings only contain the caller's workspace
+    let chats: Vec<Chat> = server.get(&foo, "/api/chats").await?;
+    assert!(chats.iter().all(|c| c.ws_id == 2));
+    let users: Vec<ChatUser> = server.get(&foo, "/api/users").await?;
+    let ids: Vec<i64> = users.iter().map(|u| u.id).collect();
+    assert_eq!(ids, vec![6, 7]);
+    let hits: Vec<Value> = server.get(&foo, "/api/search?q=hello").await?;
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0]["chat_id"], 5);
+
+    // can't put users of another workspace into a chat
+    let body = json!({ "members": [6, 1], "public": false });
+    let status = server
+        .call(&foo, Method::POST, "/api/chats", Some(body))
+        .await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // files of another workspace can't be read or attached
+    let url = server.upload(&acme).await?;
+    let file = state
+        .find_file_info(&url.parse::<ChatFile>()?)
+        .await?
+        .expect("file should be recorded");
+    let status = server
+        .call(&foo, Method::GET, &format!("/api{}", url), None)
+        .await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let status = server
+        .call(&foo, Method::GET, &format!("/api/files/{}/meta", file.id), None)
+        .await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let status = server
+        .call(&acme, Method::GET, &format!("/api/files/{}/meta", file.id), None)
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+    let body = json!({ "content": "hello", "files": [&url] });
+    let status = server
+        .call(&foo, Method::POST, "/api/chats/5", Some(body))
+        .await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn token_should_be_scoped_to_the_active_workspace() -> Result<()> {
+    let (_tdb, state) = chat_server::AppState::new_for_test().await?;
+    let server = ChatServer::new(state).await?;
+    let acme = server.signin("tchen@acme.org").await?;
+    let foo = server.signin("frank@foo.org").await?;
+
+    // frank of foo joins acme too
+    let invite = server.post(&acme, "/api/invites", json!({})).await?;
+    let body = json!({ "invite": invite["token"] });
+    let ret = server.post(&foo, "/api/workspaces/join", body).await?;
+    let frank_acme = ret["token"].as_str().expect("token").to_string();
+
+    let workspaces: Vec<Value> = server.get(&foo, "/api/workspaces").await?;
+    let names: Vec<_> = workspaces
+        .iter()
+        .map(|w| w["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, vec!["foo", "acme"]);
+
+    let chats: Vec<Chat> = server.get(&frank_acme, "/api/chats").await?;
+    assert!(!chats.is_empty() && chats.iter().all(|c| c.ws_id == 1));
+    let users: Vec<ChatUser> = server.get(&frank_acme, "/api/users").await?;
+    assert_eq!(users.len(), 6);
+    // the token of foo still sees foo only
+    let chats: Vec<Chat> = server.get(&foo, "/api/chats").await?;
+    assert!(chats.iter().all(|c| c.ws_id == 2));
+
+    let ret = server
+        .post(&frank_acme, "/api/workspaces/2/switch", json!({}))
+        .await?;
+    let token = ret["token"].as_str().expect("token");
+    let users: Vec<ChatUser> = server.get(token, "/api/users").await?;
+    assert_eq!(users.len(), 2);
+    // signing in again lands in the workspace switched to last
+    let token = server.signin("frank@foo.org").await?;
+    let chats: Vec<Chat> = server.get(&token, "/api/chats").await?;
+    assert!(chats.iter().all(|c| c.ws_id == 2));
+
+    let status = server
+        .call(&foo, Method::POST, "/api/workspaces/3/switch", None)
+        .await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    Ok(())
+}
+
+#[tokio::test]
+async fn chat_changes_should_need_a_role() -> Result<()> {
+    let (_tdb, state) = chat_server::AppState::new_for_test().await?;
+    let server = ChatServer::new(state).await?;
+    let owner = server.signin("tchen@acme.org").await?;
+    let alice = server.signin("alice@acme.org").await?;
+
+    // chat 2 is the private channel of users 1, 2 and 3, chat 3 alice's single chat with 1
+    let body = json!({ "members": [1, 2] });
+    let status = server
+        .call(&alice, Method::PATCH, "/api/chats/2", Some(body))
+        .await?;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let status = server
+        .call(&alice, Method::DELETE, "/api/chats/2", None)
+        .await?;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let chat: Chat = server.get(&owner, "/api/chats/2").await?;
+    assert_eq!(chat.members, vec![1, 2, 3]);
+
+    // adding someone or leaving is fine
+    let body = json!({ "members": [1, 2, 3, 4] });
+    let status = server
+        .call(&alice, Method::PATCH, "/api/chats/2", Some(body))
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+    let body = json!({ "members": [1, 3, 4] });
+    let status = server
+        .call(&alice, Method::PATCH, "/api/chats/2", Some(body))
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let status = server
+        .call(&alice, Method::DELETE, "/api/chats/3", None)
+        .await?;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let status = server
+        .call(&owner, Method::DELETE, "/api/chats/2", None)
+        .await?;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    Ok(())
+}
+
+#[tokio::test]
+async fn workspace_administration_should_need_a_role() -> Result<()> {
+    let (_tdb, state) = chat_server::AppState::new_for_test().await?;
+    let server = ChatServer::new(state).await?;
+    let owner = server.signin("tchen@acme.org").await?;
+    let alice = server.signin("alice@acme.org").await?;
+
+    // members may look but not change anything
+    let ws: Value = server.get(&alice, "/api/workspace").await?;
+    assert_eq!(ws["name"], "acme");
+    let members: Vec<Value> = server.get(&alice, "/api/workspace/members").await?;
+    assert_eq!(members[0]["role"], "owner");
+    let routes = [
+        (Method::PATCH, "/api/workspace", Some(json!({ "name": "mine" }))),
+        (Method::DELETE, "/api/workspace", None),
+        (Method::POST, "/api/workspace/members/3/deactivate", None),
+    ];
+    for (method, path, body) in routes {
+        let status = server.call(&alice, method.clone(), path, body).await?;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{method} {path}");
+    }
+
+    let body = json!({ "name": "acme corp", "retention_days": 90 });
+    let status = server
+        .call(&owner, Method::PATCH, "/api/workspace", Some(body))
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+
+    // a deactivated member's token stops working right away
+    let status = server
+        .call(&owner, Method::POST, "/api/workspace/members/2/deactivate", None)
+        .await?;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let status = server.call(&alice, Method::GET, "/api/chats", None).await?;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let users: Vec<ChatUser> = server.get(&owner, "/api/users").await?;
+    assert!(users.iter().all(|u| u.id != 2));
+
+    // tokens issued before an ownership transfer don't carry the old role anymore
+    let body = json!({ "owner_id": 3 });
+    let status = server
+        .call(&owner, Method::PATCH, "/api/workspace", Some(body))
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+    let status = server
+        .call(&owner, Method::DELETE, "/api/workspace", None)
+        .await?;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let admin = server.signin("tchen@acme.org").await?;
+    let status = server
+        .call(&admin, Method::DELETE, "/api/workspace", None)
+        .await?;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let ws: Value = server.get(&admin, "/api/workspace").await?;
+    assert_eq!(ws["owner_id"], 3);
+    Ok(())
+}
+
+#[tokio::test]
+async fn public_channel_should_be_previewed_and_joined() -> Result<()> {
+    let (_tdb, state) = chat_server::AppState::new_for_test().await?;
+    let server = ChatServer::new(state).await?;
+    let alice = server.signin("alice@acme.org").await?;
+    let foo = server.signin("frank@foo.org").await?;
+
+    // chat 1 is the public channel general of acme
+    let chat = server.post(&alice, "/api/chats/1/leave", json!({})).await?;
+    assert!(!chat["members"].as_array().unwrap().contains(&json!(2)));
+    let channels: Vec<Value> = server.get(&alice, "/api/channels").await?;
+    assert_eq!(channels[0]["joined"], false);
+
+    // read-only until joined
+    let _: Vec<Value> = server.get(&alice, "/api/chats/1/messages?limit=10").await?;
+    let body = json!({ "content": "hello", "files": [] });
+    let status = server
+        .call(&alice, Method::POST, "/api/chats/1", Some(body.clone()))
+        .await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    server.post(&alice, "/api/chats/1/join", json!({})).await?;
+    let status = server
+        .call(&alice, Method::POST, "/api/chats/1", Some(body))
+        .await?;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // other chats and workspaces stay closed, alice isn't in group 4
+    let status = server
+        .call(&alice, Method::GET, "/api/chats/4/messages?limit=10", None)
+        .await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let status = server
+        .call(&alice, Method::POST, "/api/chats/4/join", None)
+        .await?;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let status = server
+        .call(&foo, Method::POST, "/api/chats/1/join", None)
+        .await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let channels: Vec<Value> = server.get(&foo, "/api/channels").await?;
+    assert!(channels.is_empty());
+    Ok(())
+}
+
+impl ChatServer {
+    async fn new(state: chat_server::AppState) -> Result<Self> {
+        let app = chat_server::get_router(state).await?;
+        let listener = TcpListener::bind(WILD_ADDR).await?;
+        let addr = listener.local_addr()?;
+
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service())
+                .await
+                .unwrap();
+        });
+
+        Ok(Self {
+            addr,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    async fn signin(&self, email: &str) -> Result<String> {
+        let res = self
+            .client
+            .post(format!("http://{}/api/signin", self.addr))
+            .json(&json!({ "email": email, "password": "123456" }))
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let ret: AuthToken = res.json().await?;
+        Ok(ret.token)
+    }
+
+    async fn call(&self, token: &str, method: Method, path: &str, body: Option<Value>) -> Result<StatusCode> {
+        let mut req = self
+            .client
+            .request(method, format!("http://{}{}", self.addr, path))
+            .bearer_auth(token);
+        if let Some(body) = body {
+            req = req.json(&body);
+        }
+        Ok(req.send().await?.status())
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(&self, token: &str, path: &str) -> Result<T> {
+        let res = self
+            .client
+            .get(format!("http://{}{}", self.addr, path))
+            .bearer_auth(token)
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        Ok(res.json().await?)
+    }
+
+    async fn post(&self, token: &str, path: &str, body: Value) -> Result<Value> {
+        let res = self
+            .client
+            .post(format!("http://{}{}", self.addr, path))
+            .bearer_auth(token)
+            .json(&body)
+            .send()
+            .await?;
+        assert!(res.status().is_success(), "POST {path}: {}", res.status());
+        Ok(res.json().await?)
+    }
+
+    async fn upload(&self, token: &str) -> Result<String> {
+        let data = include_bytes!("../Cargo.toml");
+        let part = Part::bytes(data.as_slice()).file_name("Cargo.toml");
+        let res = self
+            .client
+            .post(format!("http://{}/api/upload", self.addr))
+            .bearer_auth(token)
+            .multipart(Form::new().part("file", part))
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let mut ret: Vec<String> = res.json().await?;
+        Ok(ret.remove(0))
+    }
+}
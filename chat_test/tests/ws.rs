@@ -0,0 +1,306 @@
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+
+use anyhow::Result;
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::{
+    net::TcpListener,
+    time::{sleep, timeout},
+};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+/*
+tchen (1) and alice (2) connect to notify_server over websocket,
+tchen pings, types in their single chat (3) and sends a message to it.
+*/
+
+#[derive(Debug, Deserialize)]
+struct AuthToken {
+    token: String,
+}
+
+type Ws = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+const WILD_ADDR: &str = "localhost:0";
+
+#[tokio::test]
+async fn websocket_should_work() -> Result<()> {
+    let (tdb, state) = chat_server::AppState::new_for_test().await?;
+    let chat_addr = serve(chat_server::get_router(state).await?).await?;
+    let mut config = notify_server::AppConfig::load()?;
+    config.server.db_url = tdb.url();
+    let notify_addr = serve(notify_server::get_router(config).await?).await?;
+
+    let tchen = signin(chat_addr, "tchen@acme.org").await?;
+    let alice = signin(chat_addr, "alice@acme.org").await?;
+    let mut tchen_ws = connect(notify_addr, &tchen).await?;
+    let mut alice_ws = connect(notify_addr, &alice).await?;
+
+    send(&mut tchen_ws, json!({ "action": "Ping" })).await?;
+    assert_eq!(recv(&mut tchen_ws).await?, json!({ "event": "Pong" }));
+
+    send(&mut tchen_ws, json!({ "action": "Typing", "chat_id": 3 })).await?;
+    let typing = recv(&mut alice_ws).await?;
+    assert_eq!(typing["event"], "Typing");
+    assert_eq!(typing["chat_id"], 3);
+    assert_eq!(typing["user_id"], 1);
+
+    // chat 5 belongs to another workspace
+    send(&mut tchen_ws, json!({ "action": "Typing", "chat_id": 5 })).await?;
+    assert_eq!(recv(&mut tchen_ws).await?["event"], "Error");
+
+    send(&mut tchen_ws, json!({ "action": "Shout" })).await?;
+    assert_eq!(recv(&mut tchen_ws).await?["event"], "Error");
+
+    // events from the database arrive the same way as over SSE
+    let res = reqwest::Client::new()
+        .post(format!("http://{}/api/chats/3", chat_addr))
+        .bearer_auth(&tchen)
+        .json(&json!({ "content": "hello", "files": [] }))
+        .send()
+        .await?;
+    assert_eq!(res.status(), 201);
+    let frame = recv(&mut alice_ws).await?;
+    assert_eq!(frame["event"], "NewMessage");
+    assert_eq!(frame["content"], "hello");
+    assert_eq!(recv(&mut tchen_ws).await?["event"], "NewMessage");
+    assert!(frame["event_id"].as_u64() > typing["event_id"].as_u64());
+
+    // edits and deletes reach the other members too
+    let path = format!("http://{}/api/chats/3/messages/{}", chat_addr, frame["id"]);
+    let client = reqwest::Client::new();
+    let res = client
+        .patch(&path)
+        .bearer_auth(&tchen)
+        .json(&json!({ "content": "hello!" }))
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let updated = recv(&mut alice_ws).await?;
+    assert_eq!(updated["event"], "MessageUpdated");
+    assert_eq!(updated["content"], "hello!");
+    let res = client.delete(&path).bearer_auth(&alice).send().await?;
+    assert_eq!(res.status(), 403);
+    let res = client.delete(&path).bearer_auth(&tchen).send().await?;
+    assert_eq!(res.status(), 204);
+    let deleted = recv(&mut alice_ws).await?;
+    assert_eq!(deleted["event"], "MessageDeleted");
+    assert_eq!(deleted["content"], "");
+    recv(&mut tchen_ws).await?;
+    recv(&mut tchen_ws).await?;
+
+    // replies only go to those who took part in the thread (alice sent message 2)
+    let res = client
+        .post(format!("http://{}/api/chats/1", chat_addr))
+        .bearer_auth(&tchen)
+        .json(&json!({ "content": "re", "files": [], "parent_id": 2 }))
+        .send()
+        .await?;
+    assert_eq!(res.status(), 201);
+    let reply = recv(&mut alice_ws).await?;
+    assert_eq!(reply["event"], "NewReply");
+    assert_eq!(reply["parent_id"], 2);
+    assert_eq!(recv(&mut tchen_ws).await?["event"], "NewReply");
+
+    // reactions are broadcast to all members with the new count
+    let res = client
+        .post(format!("http://{}/api/chats/1/messages/2/reactions/👍", chat_addr))
+        .bearer_auth(&tchen)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let reaction = recv(&mut alice_ws).await?;
+    assert_eq!(reaction["event"], "ReactionChanged");
+    assert_eq!(reaction["emoji"], "👍");
+    assert_eq!(reaction["count"], 1);
+    assert_eq!(recv(&mut tchen_ws).await?["event"], "ReactionChanged");
+
+    // read positions go to the reader's devices, in single chats to the peer as well
+    send(
+        &mut alice_ws,
+        json!({ "action": "MarkRead", "chat_id": 1, "message_id": 2 }),
+    )
+    .await?;
+    let read = recv(&mut alice_ws).await?;
+    assert_eq!(read["event"], "ReadUpdated");
+    assert_eq!(read["last_read_message_id"], 2);
+    let res = client
+        .post(format!("http://{}/api/chats/3/read", chat_addr))
+        .bearer_auth(&tchen)
+        .json(&json!({ "message_id": frame["id"] }))
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let read = recv(&mut alice_ws).await?;
+    assert_eq!(read["event"], "ReadUpdated");
+    assert_eq!(read["user_id"], 1);
+    assert_eq!(recv(&mut tchen_ws).await?["event"], "ReadUpdated");
+
+    // reconnecting after the typing event replays what came after it
+    let mut alice_ws = connect(notify_addr, &format!("{}&last_event_id={}", alice, typing["event_id"])).await?;
+    assert_eq!(recv(&mut alice_ws).await?, frame);
+
+    // a gap which can't be replayed asks the client to resync
+    let mut alice_ws = connect(notify_addr, &format!("{}&last_event_id=1", alice)).await?;
+    assert_eq!(recv(&mut alice_ws).await?["event"], "Resync");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn connections_should_be_released() -> Result<()> {
+    let (tdb, state) = chat_server::AppState::new_for_test().await?;
+    let chat_addr = serve(chat_server::get_router(state).await?).await?;
+    let mut config = notify_server::AppConfig::load()?;
+    config.server.db_url = tdb.url();
+    config.server.channel_idle_ttl = 0;
+    let notify_addr = serve(notify_server::get_router(config).await?).await?;
+
+    let emails = [
+        "tchen@acme.org",
+        "alice@acme.org",
+        "bob@acme.org",
+        "charlie@acme.org",
+        "daisy@acme.org",
+    ];
+    let mut clients = vec![];
+    for email in emails {
+        let token = signin(chat_addr, email).await?;
+        for _ in 0..4 {
+            clients.push(connect(notify_addr, &token).await?);
+        }
+    }
+    // make sure every connection has subscribed
+    for ws in clients.iter_mut() {
+        send(ws, json!({ "action": "Ping" })).await?;
+        assert_eq!(recv(ws).await?["event"], "Pong");
+    }
+    let m = metrics(notify_addr).await?;
+    assert_eq!(m["notify_connected_users"], 5);
+    assert_eq!(m["notify_connections"], 20);
+
+    for mut ws in clients.drain(10..) {
+        ws.close(None).await?;
+    }
+    wait_for(notify_addr, "notify_connections", 10).await?;
+    assert_eq!(metrics(notify_addr).await?["notify_connected_users"], 3);
+
+    for mut ws in clients {
+        ws.close(None).await?;
+    }
+    wait_for(notify_addr, "notify_user_channels", 0).await?;
+    assert_eq!(metrics(notify_addr).await?["notify_connections"], 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn listener_should_recover() -> Result<()> {
+    let (tdb, state) = chat_server::AppState::new_for_test().await?;
+    let chat_addr = serve(chat_server::get_router(state).await?).await?;
+    let mut config = notify_server::AppConfig::load()?;
+    config.server.db_url = tdb.url();
+    let notify_addr = serve(notify_server::get_router(config).await?).await?;
+    let tchen = signin(chat_addr, "tchen@acme.org").await?;
+    let mut ws = connect(notify_addr, &tchen).await?;
+    send(&mut ws, json!({ "action": "Ping" })).await?;
+    assert_eq!(recv(&mut ws).await?["event"], "Pong");
+
+    // a malformed payload is skipped
+    let pool = tdb.get_pool().await;
+    sqlx::query("SELECT pg_notify('chat_updated', 'garbage')")
+        .execute(&pool)
+        .await?;
+
+    // kill the listener's connection, clients are told to resync once it's back
+    sqlx::query(
+        "SELECT pg_terminate_backend(pid) FROM pg_stat_activity
+         WHERE datname = current_database() AND query LIKE 'LISTEN%'",
+    )
+    .execute(&pool)
+    .await?;
+    assert_eq!(recv(&mut ws).await?["event"], "Resync");
+    let res = reqwest::get(format!("http://{}/health", notify_addr)).await?;
+    assert_eq!(res.status(), 200);
+    let health: Value = res.json().await?;
+    assert_eq!(health["reconnects"], 1);
+
+    // and notifications flow again
+    let res = reqwest::Client::new()
+        .post(format!("http://{}/api/chats/3", chat_addr))
+        .bearer_auth(&tchen)
+        .json(&json!({ "content": "hello", "files": [] }))
+        .send()
+        .await?;
+    assert_eq!(res.status(), 201);
+    assert_eq!(recv(&mut ws).await?["event"], "NewMessage");
+    Ok(())
+}
+
+async fn serve(app: axum::Router) -> Result<SocketAddr> {
+    let listener = TcpListener::bind(WILD_ADDR).await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap();
+    });
+    Ok(addr)
+}
+
+async fn signin(addr: SocketAddr, email: &str) -> Result<String> {
+    let res = reqwest::Client::new()
+        .post(format!("http://{}/api/signin", addr))
+        .json(&json!({ "email": email, "password": "123456" }))
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let ret: AuthToken = res.json().await?;
+    Ok(ret.token)
+}
+
+async fn connect(addr: SocketAddr, token: &str) -> Result<Ws> {
+    let (ws, _) = connect_async(format!("ws://{}/ws?access_token={}", addr, token)).await?;
+    Ok(ws)
+}
+
+async fn send(ws: &mut Ws, frame: Value) -> Result<()> {
+    ws.send(Message::Text(frame.to_string())).await?;
+    Ok(())
+}
+
+async fn metrics(addr: SocketAddr) -> Result<HashMap<String, u64>> {
+    let body = reqwest::get(format!("http://{}/metrics", addr))
+        .await?
+        .text()
+        .await?;
+    let ret = body
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once(' '))
+        .filter_map(|(k, v)| Some((k.to_string(), v.parse().ok()?)))
+        .collect();
+    Ok(ret)
+}
+
+/// closed connections are released asynchronously, poll until the metric settles
+async fn wait_for(addr: SocketAddr, name: &str, value: u64) -> Result<()> {
+    for _ in 0..50 {
+        if metrics(addr).await?[name] == value {
+            return Ok(());
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    anyhow::bail!("{} didn't reach {}", name, value)
+}
+
+async fn recv(ws: &mut Ws) -> Result<Value> {
+    loop {
+        let msg = timeout(Duration::from_secs(5), ws.next())
+            .await?
+            .expect("websocket closed")?;
+        if let Message::Text(text) = msg {
+            return Ok(serde_json::from_str(&text)?);
+        }
+    }
+}
@@ -0,0 +1,3 @@
+ALTER TABLE messages
+  DROP CONSTRAINT messages_chat_id_fkey,
+  ADD CONSTRAINT messages_chat_id_fkey FOREIGN KEY (chat_id) REFERENCES chats(id);
@@ -0,0 +1,19 @@
+-- opaque rotating refresh tokens, tokens rotated from the same signin share a family
+CREATE TABLE IF NOT EXISTS refresh_tokens(
+  id bigserial PRIMARY KEY,
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  -- sha256 of the token, the token itself is never stored
+  token_hash char(64) NOT NULL UNIQUE,
+  family char(32) NOT NULL,
+  expires_at timestamptz NOT NULL,
+  revoked_at timestamptz,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_family_index ON refresh_tokens(family);
+
+-- deny list of access tokens (by jti) revoked before they expire
+CREATE TABLE IF NOT EXISTS revoked_tokens(
+  jti varchar(64) PRIMARY KEY,
+  expires_at timestamptz NOT NULL
+);
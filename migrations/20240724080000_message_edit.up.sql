@@ -0,0 +1,40 @@
+-- messages are edited in place and deleted as tombstones (content and files cleared)
+ALTER TABLE messages
+  ADD COLUMN edited_at timestamptz,
+  ADD COLUMN deleted_at timestamptz;
+
+-- notify about new, edited and deleted messages with the message and chat members
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+  CHANNEL text;
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    CHANNEL := 'chat_message_created';
+  ELSIF NEW.deleted_at IS DISTINCT FROM OLD.deleted_at THEN
+    CHANNEL := 'chat_message_deleted';
+  ELSIF NEW.edited_at IS DISTINCT FROM OLD.edited_at THEN
+    CHANNEL := 'chat_message_updated';
+  ELSE
+    RETURN NEW;
+  END IF;
+  RAISE NOTICE 'add_to_message: % %', CHANNEL, NEW;
+  SELECT
+    members INTO USERS
+  FROM
+    chats
+  WHERE
+    id = NEW.chat_id;
+  PERFORM
+    pg_notify(CHANNEL, json_build_object('message', NEW, 'members', USERS)::text);
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER add_to_message_trigger
+  AFTER INSERT OR UPDATE ON messages
+  FOR EACH ROW
+  EXECUTE FUNCTION add_to_message();
@@ -0,0 +1,36 @@
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+  CHANNEL text;
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    CHANNEL := 'chat_message_created';
+  ELSIF NEW.deleted_at IS DISTINCT FROM OLD.deleted_at THEN
+    CHANNEL := 'chat_message_deleted';
+  ELSIF NEW.edited_at IS DISTINCT FROM OLD.edited_at THEN
+    CHANNEL := 'chat_message_updated';
+  ELSE
+    RETURN NEW;
+  END IF;
+  RAISE NOTICE 'add_to_message: % %', CHANNEL, NEW;
+  SELECT
+    members INTO USERS
+  FROM
+    chats
+  WHERE
+    id = NEW.chat_id;
+  PERFORM
+    pg_notify(CHANNEL, json_build_object('message', NEW, 'members', USERS)::text);
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+DROP INDEX IF EXISTS parent_id_index;
+
+ALTER TABLE messages
+  DROP COLUMN parent_id,
+  DROP COLUMN reply_count,
+  DROP COLUMN last_reply_at;
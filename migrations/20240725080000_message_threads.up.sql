@@ -0,0 +1,51 @@
+-- replies point to a top-level message, which keeps count of them
+ALTER TABLE messages
+  ADD COLUMN parent_id bigint REFERENCES messages(id) ON DELETE CASCADE,
+  ADD COLUMN reply_count integer NOT NULL DEFAULT 0,
+  ADD COLUMN last_reply_at timestamptz;
+
+CREATE INDEX IF NOT EXISTS parent_id_index ON messages(parent_id, id DESC)
+WHERE
+  parent_id IS NOT NULL;
+
+-- replies only go to chat members who took part in the thread
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+  CHANNEL text;
+BEGIN
+  IF TG_OP = 'INSERT' AND NEW.parent_id IS NOT NULL THEN
+    CHANNEL := 'chat_message_replied';
+  ELSIF TG_OP = 'INSERT' THEN
+    CHANNEL := 'chat_message_created';
+  ELSIF NEW.deleted_at IS DISTINCT FROM OLD.deleted_at THEN
+    CHANNEL := 'chat_message_deleted';
+  ELSIF NEW.edited_at IS DISTINCT FROM OLD.edited_at THEN
+    CHANNEL := 'chat_message_updated';
+  ELSE
+    RETURN NEW;
+  END IF;
+  RAISE NOTICE 'add_to_message: % %', CHANNEL, NEW;
+  SELECT
+    members INTO USERS
+  FROM
+    chats
+  WHERE
+    id = NEW.chat_id;
+  IF CHANNEL = 'chat_message_replied' THEN
+    SELECT
+      array_agg(DISTINCT sender_id) INTO USERS
+    FROM
+      messages
+    WHERE (id = NEW.parent_id
+      OR parent_id = NEW.parent_id)
+    AND sender_id = ANY (USERS);
+  END IF;
+  PERFORM
+    pg_notify(CHANNEL, json_build_object('message', NEW, 'members', USERS)::text);
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
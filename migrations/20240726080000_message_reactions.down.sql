@@ -0,0 +1,3 @@
+DROP TRIGGER IF EXISTS message_reaction_changed_trigger ON message_reactions;
+DROP FUNCTION IF EXISTS message_reaction_changed();
+DROP TABLE IF EXISTS message_reactions;
@@ -0,0 +1,55 @@
+-- one row per user and emoji on a message
+CREATE TABLE IF NOT EXISTS message_reactions(
+  message_id bigint NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  emoji varchar(32) NOT NULL,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (message_id, emoji, user_id)
+);
+
+-- notify chat members with the new count of the emoji on the message
+CREATE OR REPLACE FUNCTION message_reaction_changed()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  R record;
+  USERS bigint[];
+  CHAT bigint;
+  CNT bigint;
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    R := NEW;
+  ELSE
+    R := OLD;
+  END IF;
+  SELECT
+    m.chat_id,
+    c.members INTO CHAT,
+    USERS
+  FROM
+    messages m
+    JOIN chats c ON c.id = m.chat_id
+  WHERE
+    m.id = R.message_id;
+  -- the message is being deleted along with its chat
+  IF CHAT IS NULL THEN
+    RETURN NULL;
+  END IF;
+  SELECT
+    count(*) INTO CNT
+  FROM
+    message_reactions
+  WHERE
+    message_id = R.message_id
+    AND emoji = R.emoji;
+  PERFORM
+    pg_notify('message_reaction_changed', json_build_object('reaction', json_build_object('chat_id', CHAT, 'message_id', R.message_id, 'user_id', R.user_id, 'emoji', R.emoji, 'count', CNT, 'added', TG_OP = 'INSERT'), 'members', USERS)::text);
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER message_reaction_changed_trigger
+  AFTER INSERT OR DELETE ON message_reactions
+  FOR EACH ROW
+  EXECUTE FUNCTION message_reaction_changed();
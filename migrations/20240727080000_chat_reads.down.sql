@@ -0,0 +1,3 @@
+DROP TRIGGER IF EXISTS chat_read_updated_trigger ON chat_reads;
+DROP FUNCTION IF EXISTS chat_read_updated();
+DROP TABLE IF EXISTS chat_reads;
@@ -0,0 +1,37 @@
+-- how far each member has read a chat
+CREATE TABLE IF NOT EXISTS chat_reads(
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  last_read_message_id bigint NOT NULL,
+  updated_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (chat_id, user_id)
+);
+
+-- the reader's other devices follow along, in single chats the peer sees it too
+CREATE OR REPLACE FUNCTION chat_read_updated()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+BEGIN
+  SELECT
+    CASE WHEN type = 'single' THEN
+      members
+    ELSE
+      ARRAY[NEW.user_id]
+    END INTO USERS
+  FROM
+    chats
+  WHERE
+    id = NEW.chat_id;
+  PERFORM
+    pg_notify('chat_read_updated', json_build_object('read', NEW, 'members', USERS)::text);
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER chat_read_updated_trigger
+  AFTER INSERT OR UPDATE ON chat_reads
+  FOR EACH ROW
+  EXECUTE FUNCTION chat_read_updated();
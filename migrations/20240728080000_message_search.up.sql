@@ -0,0 +1,2 @@
+-- full-text search on message content, queries must use the same expression to hit the index
+CREATE INDEX IF NOT EXISTS messages_content_search_index ON messages USING GIN (to_tsvector('simple', content));
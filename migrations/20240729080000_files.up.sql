@@ -0,0 +1,14 @@
+-- metadata of uploaded files, the content stays content addressed in the file store.
+-- the same content uploaded twice to a workspace is one file, the first upload names it.
+CREATE TABLE IF NOT EXISTS files(
+  id bigserial PRIMARY KEY,
+  ws_id bigint NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+  uploader_id bigint REFERENCES users(id) ON DELETE SET NULL,
+  hash char(40) NOT NULL,
+  ext text NOT NULL,
+  name text NOT NULL,
+  size bigint NOT NULL,
+  mime text NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (ws_id, hash, ext)
+);
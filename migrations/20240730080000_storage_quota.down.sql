@@ -0,0 +1,3 @@
+ALTER TABLE workspaces
+  DROP COLUMN storage_used,
+  DROP COLUMN storage_quota;
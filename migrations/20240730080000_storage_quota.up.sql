@@ -0,0 +1,16 @@
+-- bytes of distinct files stored by a workspace, checked against its quota on upload.
+-- without a quota of its own the server default applies.
+ALTER TABLE workspaces
+  ADD COLUMN storage_used bigint NOT NULL DEFAULT 0,
+  ADD COLUMN storage_quota bigint;
+
+UPDATE
+  workspaces w
+SET
+  storage_used =(
+    SELECT
+      coalesce(sum(size), 0)
+    FROM
+      files f
+    WHERE
+      f.ws_id = w.id);
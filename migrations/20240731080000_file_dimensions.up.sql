@@ -0,0 +1,4 @@
+-- pixels of uploaded images, thumbnails are kept next to the original in the file store
+ALTER TABLE files
+  ADD COLUMN width int,
+  ADD COLUMN height int;
@@ -0,0 +1,4 @@
+ALTER TABLE files
+  DROP COLUMN uploaded_at;
+DROP INDEX IF EXISTS messages_files_idx;
+DROP FUNCTION IF EXISTS file_url(files);
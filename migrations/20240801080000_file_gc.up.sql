@@ -0,0 +1,21 @@
+-- the url messages refer to a file by, see ChatFile::url
+CREATE OR REPLACE FUNCTION file_url(f files)
+  RETURNS text
+  AS $$
+  SELECT
+    '/files/' || f.ws_id || '/' || substr(f.hash, 1, 3) || '/' || substr(f.hash, 4, 3) || '/' || substr(f.hash, 7) || '.' || f.ext;
+$$
+LANGUAGE sql
+IMMUTABLE;
+
+-- the messages referencing a file, files referenced by none are garbage
+CREATE INDEX IF NOT EXISTS messages_files_idx ON messages USING gin(files);
+
+-- last time the content was uploaded, uploading an unused file again restarts its grace period
+ALTER TABLE files
+  ADD COLUMN uploaded_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP;
+
+UPDATE
+  files
+SET
+  uploaded_at = created_at;
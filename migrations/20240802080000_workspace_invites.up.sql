@@ -0,0 +1,17 @@
+-- invites to join a workspace on signup, only the hash of the token is kept.
+-- without max_uses an invite can be used until it expires.
+CREATE TABLE IF NOT EXISTS workspace_invites(
+  id bigserial PRIMARY KEY,
+  ws_id bigint NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+  token_hash char(64) NOT NULL UNIQUE,
+  created_by bigint REFERENCES users(id) ON DELETE SET NULL,
+  email text,
+  domain text,
+  max_uses int,
+  uses int NOT NULL DEFAULT 0,
+  expires_at timestamptz NOT NULL,
+  revoked_at timestamptz,
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS workspace_invites_ws_id_idx ON workspace_invites(ws_id);
@@ -0,0 +1,5 @@
+ALTER TABLE workspace_invites
+  DROP COLUMN role;
+ALTER TABLE users
+  DROP COLUMN role;
+DROP TYPE IF EXISTS workspace_role;
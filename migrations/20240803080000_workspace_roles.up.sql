@@ -0,0 +1,24 @@
+-- what a user may do in their workspace, see chat_core::Role
+CREATE TYPE workspace_role AS ENUM(
+  'owner',
+  'admin',
+  'member',
+  'guest'
+);
+
+ALTER TABLE users
+  ADD COLUMN role workspace_role NOT NULL DEFAULT 'member';
+
+UPDATE
+  users u
+SET
+  role = 'owner'
+FROM
+  workspaces w
+WHERE
+  w.owner_id = u.id
+  AND w.id = u.ws_id;
+
+-- the role a user signing up with the invite gets
+ALTER TABLE workspace_invites
+  ADD COLUMN role workspace_role NOT NULL DEFAULT 'member';
@@ -0,0 +1,14 @@
+ALTER TABLE refresh_tokens
+  DROP COLUMN ws_id;
+ALTER TABLE users
+  ADD COLUMN role workspace_role NOT NULL DEFAULT 'member';
+UPDATE
+  users u
+SET
+  role = m.role
+FROM
+  workspace_members m
+WHERE
+  m.user_id = u.id
+  AND m.ws_id = u.ws_id;
+DROP TABLE IF EXISTS workspace_members;
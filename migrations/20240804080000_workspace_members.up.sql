@@ -0,0 +1,38 @@
+-- the workspaces a user belongs to, with their role in each. users.ws_id is only the
+-- workspace they are signed in to by default now, tokens carry the active one.
+CREATE TABLE IF NOT EXISTS workspace_members(
+  ws_id bigint NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  role workspace_role NOT NULL DEFAULT 'member',
+  joined_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (ws_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS workspace_members_user_id_index ON workspace_members(user_id);
+
+INSERT INTO workspace_members(ws_id, user_id, role)
+SELECT
+  ws_id,
+  id,
+  role
+FROM
+  users;
+
+ALTER TABLE users
+  DROP COLUMN role;
+
+-- a refresh token renews access to the workspace it was issued for
+ALTER TABLE refresh_tokens
+  ADD COLUMN ws_id bigint REFERENCES workspaces(id) ON DELETE CASCADE;
+
+UPDATE
+  refresh_tokens r
+SET
+  ws_id = u.ws_id
+FROM
+  users u
+WHERE
+  u.id = r.user_id;
+
+ALTER TABLE refresh_tokens
+  ALTER COLUMN ws_id SET NOT NULL;
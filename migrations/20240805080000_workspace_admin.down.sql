@@ -0,0 +1,9 @@
+ALTER TABLE chats
+  DROP CONSTRAINT chats_ws_id_fkey,
+  ADD CONSTRAINT chats_ws_id_fkey FOREIGN KEY (ws_id) REFERENCES workspaces(id);
+ALTER TABLE workspace_members
+  DROP COLUMN deactivated_at,
+  DROP COLUMN last_seen_at;
+ALTER TABLE workspaces
+  DROP COLUMN retention_days,
+  DROP COLUMN default_channels;
@@ -0,0 +1,15 @@
+-- chats new members are added to, and days messages are kept (0 keeps them forever)
+ALTER TABLE workspaces
+  ADD COLUMN default_channels bigint[] NOT NULL DEFAULT '{}',
+  ADD COLUMN retention_days int NOT NULL DEFAULT 0;
+
+-- a deactivated member can't sign in to the workspace and is hidden from it,
+-- last_seen_at is when tokens were last issued to them for it
+ALTER TABLE workspace_members
+  ADD COLUMN last_seen_at timestamptz,
+  ADD COLUMN deactivated_at timestamptz;
+
+-- deleting a workspace takes its chats with it
+ALTER TABLE chats
+  DROP CONSTRAINT chats_ws_id_fkey,
+  ADD CONSTRAINT chats_ws_id_fkey FOREIGN KEY (ws_id) REFERENCES workspaces(id) ON DELETE CASCADE;
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS chats_public_channel_index;
+ALTER TABLE chats
+  DROP COLUMN topic;
@@ -0,0 +1,7 @@
+-- what a chat is about, shown when browsing public channels
+ALTER TABLE chats
+  ADD COLUMN topic varchar(250);
+
+CREATE INDEX IF NOT EXISTS chats_public_channel_index ON chats(ws_id)
+WHERE
+  type = 'public_channel';
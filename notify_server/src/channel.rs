@@ -0,0 +1,246 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use futures::{stream, Stream, StreamExt};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+
+use crate::notify::AppEvent;
+
+const CHANNEL_CAPACITY: usize = 256;
+const REPLAY_CAPACITY: usize = 256;
+
+/// an event as delivered to a user, `id` is what clients send back as `Last-Event-ID`.
+/// it's serialized as `event_id` next to the event fields (which may have an `id` of their own).
+#[derive(Debug, Serialize)]
+pub struct IdEvent {
+    #[serde(rename = "event_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(flatten)]
+    pub event: Arc<AppEvent>,
+}
+
+/// per-user broadcast channel with a bounded buffer of recent events for replay
+pub struct UserChannel {
+    tx: broadcast::Sender<Arc<IdEvent>>,
+    replay: Mutex<Replay>,
+    live: Arc<Mutex<Live>>,
+}
+
+struct Live {
+    subscribers: usize,
+    // when the last subscriber left, the channel is dropped after being idle for a while
+    idle_since: Instant,
+}
+
+/// decrements the subscriber count when the subscription stream is dropped
+struct SubscriberGuard(Arc<Mutex<Live>>);
+
+struct Replay {
+    events: VecDeque<Arc<IdEvent>>,
+    // highest event id which may have been missed and isn't in `events` anymore
+    floor: u64,
+}
+
+impl IdEvent {
+    fn resync(id: Option<u64>) -> Arc<Self> {
+        Arc::new(Self {
+            id,
+            event: Arc::new(AppEvent::Resync),
+        })
+    }
+}
+
+impl UserChannel {
+    pub fn new(ids: &AtomicU64) -> Self {
+        let (tx, _) = broadcast::channel(CHANNEL_CAPACITY);
+        let replay = Replay {
+            events: VecDeque::with_capacity(REPLAY_CAPACITY),
+            floor: ids.load(Ordering::SeqCst).saturating_sub(1),
+        };
+        let live = Live {
+            subscribers: 0,
+            idle_since: Instant::now(),
+        };
+        Self {
+            tx,
+            replay: Mutex::new(replay),
+            live: Arc::new(Mutex::new(live)),
+        }
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.live.lock().expect("live lock poisoned").subscribers
+    }
+
+    /// no subscribers for at least `ttl`
+    pub fn is_idle(&self, ttl: Duration) -> bool {
+        let live = self.live.lock().expect("live lock poisoned");
+        live.subscribers == 0 && live.idle_since.elapsed() >= ttl
+    }
+
+    /// assign the next id to the event, buffer it and send it to the live subscribers
+    pub fn send(&self, ids: &AtomicU64, event: Arc<AppEvent>) {
+        // ids are taken under the lock so they're increasing within the channel
+        let mut replay = self.replay.lock().expect("replay lock poisoned");
+        let id = ids.fetch_add(1, Ordering::SeqCst);
+        let event = Arc::new(IdEvent { id: Some(id), event });
+        if replay.events.len() == REPLAY_CAPACITY {
+            if let Some(evicted) = replay.events.pop_front() {
+                replay.floor = evicted.id.unwrap_or_default();
+            }
+        }
+        replay.events.push_back(event.clone());
+        // no live subscribers is fine, the event is kept for replay
+        let _ = self.tx.send(event);
+    }
+
+    /// events after `last_event_id` followed by live ones. if some of them are gone (or the
+    /// subscriber falls behind) a `Resync` event is sent instead and the client should refetch.
+    pub fn subscribe(&self, last_event_id: Option<u64>) -> impl Stream<Item = Arc<IdEvent>> {
+        let replay = self.replay.lock().expect("replay lock poisoned");
+        let rx = self.tx.subscribe();
+        let missed = match last_event_id {
+            None => vec![],
+            Some(last) if last < replay.floor => {
+                let latest = replay
+                    .events
+                    .back()
+                    .and_then(|e| e.id)
+                    .unwrap_or(replay.floor);
+                vec![IdEvent::resync(Some(latest))]
+            }
+            Some(last) => replay
+                .events
+                .iter()
+                .filter(|e| e.id.is_some_and(|id| id > last))
+                .cloned()
+                .collect(),
+        };
+        drop(replay);
+
+        self.live.lock().expect("live lock poisoned").subscribers += 1;
+        let guard = SubscriberGuard(self.live.clone());
+        let live = BroadcastStream::new(rx).map(|v| match v {
+            Ok(event) => event,
+            Err(BroadcastStreamRecvError::Lagged(_)) => IdEvent::resync(None),
+        });
+        stream::iter(missed).chain(live).map(move |event| {
+            // the guard lives as long as the stream
+            let _ = &guard;
+            event
+        })
+    }
+}
+
+impl Drop for SubscriberGuard {
+    fn drop(&mut self) {
+        let mut live = self.0.lock().expect("live lock poisoned");
+        live.subscribers -= 1;
+        if live.subscribers == 0 {
+            live.idle_since = Instant::now();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chat_core::{Chat, ChatType};
+
+    use super::*;
+
+    fn event(id: i64) -> Arc<AppEvent> {
+        Arc::new(AppEvent::NewChat(Chat {
+            id,
+            ws_id: 1,
+            name: None,
+            topic: None,
+            r#type: ChatType::Group,
+            members: vec![1, 2, 3],
+            created_at: chrono::Utc::now(),
+            unread: 0,
+            last_message: None,
+        }))
+    }
+
+    async fn ids(stream: impl Stream<Item = Arc<IdEvent>>, n: usize) -> Vec<(Option<u64>, &'static str)> {
+        stream
+            .take(n)
+            .map(|e| (e.id, e.event.name()))
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn replay_should_work() {
+        let ids_counter = AtomicU64::new(100);
+        let channel = UserChannel::new(&ids_counter);
+        for i in 0..3 {
+            channel.send(&ids_counter, event(i));
+        }
+
+        let stream = channel.subscribe(Some(100));
+        channel.send(&ids_counter, event(3));
+        let ret = ids(stream, 3).await;
+        assert_eq!(
+            ret,
+            vec![(Some(101), "NewChat"), (Some(102), "NewChat"), (Some(103), "NewChat")]
+        );
+
+        // a stale id from before the channel existed can't be replayed
+        let ret = ids(channel.subscribe(Some(50)), 1).await;
+        assert_eq!(ret, vec![(Some(103), "Resync")]);
+    }
+
+    #[tokio::test]
+    async fn evicted_events_should_resync() {
+        let ids_counter = AtomicU64::new(1);
+        let channel = UserChannel::new(&ids_counter);
+        for i in 0..REPLAY_CAPACITY + 10 {
+            channel.send(&ids_counter, event(i as _));
+        }
+        let latest = Some(REPLAY_CAPACITY as u64 + 10);
+        assert_eq!(ids(channel.subscribe(Some(5)), 1).await, vec![(latest, "Resync")]);
+
+        // still in the buffer
+        let ret = ids(channel.subscribe(Some(REPLAY_CAPACITY as u64 + 8)), 2).await;
+        assert_eq!(
+            ret,
+            vec![(Some(REPLAY_CAPACITY as u64 + 9), "NewChat"), (latest, "NewChat")]
+        );
+    }
+
+    #[tokio::test]
+    async fn subscribers_should_be_counted() {
+        let ids_counter = AtomicU64::new(1);
+        let channel = UserChannel::new(&ids_counter);
+        let s1 = channel.subscribe(None);
+        let s2 = channel.subscribe(None);
+        assert_eq!(channel.subscribers(), 2);
+        drop(s1);
+        assert_eq!(channel.subscribers(), 1);
+        assert!(!channel.is_idle(Duration::ZERO));
+        drop(s2);
+        assert_eq!(channel.subscribers(), 0);
+        assert!(channel.is_idle(Duration::ZERO));
+        assert!(!channel.is_idle(Duration::from_secs(60)));
+    }
+
+    #[tokio::test]
+    async fn lagged_subscriber_should_resync() {
+        let ids_counter = AtomicU64::new(1);
+        let channel = UserChannel::new(&ids_counter);
+        let stream = channel.subscribe(None);
+        for i in 0..CHANNEL_CAPACITY + 1 {
+            channel.send(&ids_counter, event(i as _));
+        }
+        assert_eq!(ids(stream, 1).await, vec![(None, "Resync")]);
+    }
+}
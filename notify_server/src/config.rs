@@ -13,13 +13,31 @@ pub struct AppConfig {
 pub struct ServerConfig {
     pub port: u16,
     pub db_url: String,
+    /// seconds a user's channel (and its replay buffer) is kept after the last client disconnected
+    #[serde(default = "default_channel_idle_ttl")]
+    pub channel_idle_ttl: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthConfig {
+    /// keys which are always accepted
+    #[serde(default)]
+    pub keys: Vec<AuthKey>,
+    /// chat_server's `/.well-known/jwks.json`, fetched on startup and when a token has an unknown kid
+    #[serde(default)]
+    pub jwks_url: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthKey {
+    pub kid: String,
     pub pk: String,
 }
 
+fn default_channel_idle_ttl() -> u64 {
+    60
+}
+
 impl AppConfig {
     pub fn load() -> Result<Self> {
         // read from  ./app.yml, or /etc/config/app.yml, or from env CHAT_CONFIG
@@ -16,6 +16,14 @@ pub enum AppError {
     IoError(#[from] std::io::Error),
     #[error("jwt error: {0}")]
     JwtError(#[from] jwt_simple::Error),
+    #[error("sql error: {0}")]
+    SqlxError(#[from] sqlx::Error),
+    #[error("fetch jwks error: {0}")]
+    JwksError(#[from] reqwest::Error),
+    #[error("token has been revoked")]
+    TokenRevoked,
+    #[error("not found: {0}")]
+    NotFound(String),
 }
 
 impl ErrorOutput {
@@ -28,6 +36,10 @@ impl IntoResponse for AppError {
         let status = match &self {
             AppError::IoError(_) => StatusCode::FORBIDDEN,
             AppError::JwtError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::SqlxError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::JwksError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::TokenRevoked => StatusCode::UNAUTHORIZED,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
         };
         (status, Json(ErrorOutput::new(self.to_string()))).into_response()
     }
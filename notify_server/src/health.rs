@@ -0,0 +1,14 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+
+use crate::AppSate;
+
+/// 200 while the postgres listener is connected, 503 otherwise
+pub(crate) async fn health_handler(State(state): State<AppSate>) -> impl IntoResponse {
+    let health = state.listener_health();
+    let status = if health.connected {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(health))
+}
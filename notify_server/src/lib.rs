@@ -1,8 +1,16 @@
+mod channel;
 mod config;
 mod error;
+mod health;
+mod metrics;
 mod notify;
 mod sse;
-use std::{ops::Deref, sync::Arc};
+mod ws;
+use std::{
+    ops::Deref,
+    sync::{atomic::AtomicU64, Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
 
 use axum::{
     middleware::from_fn_with_state,
@@ -10,20 +18,31 @@ use axum::{
     routing::get,
     Router,
 };
+use channel::{IdEvent, UserChannel};
 use chat_core::{
     middlewares::{verify_token, TokenVerify},
-    DecodingKey, User,
+    DecodingKey, Jwks, User,
 };
+use chrono::Utc;
 pub use config::AppConfig;
+use config::AuthConfig;
 use dashmap::DashMap;
 use error::AppError;
+use futures::Stream;
+use health::health_handler;
+use metrics::metrics_handler;
 pub use notify::setup_pg_listener;
-use notify::AppEvent;
+use notify::{AppEvent, ListenerHealth};
+use sqlx::PgPool;
 use sse::sse_handler;
-use tokio::sync::broadcast;
+use tracing::{info, warn};
+use ws::ws_handler;
 
 const INDEX_HTML: &str = include_str!("../index.html");
-pub type UserMap = Arc<DashMap<u64, broadcast::Sender<Arc<AppEvent>>>>;
+// tokens with an unknown kid trigger a refetch, but not more often than this
+const JWKS_REFETCH_INTERVAL: Duration = Duration::from_secs(30);
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+pub type UserMap = Arc<DashMap<u64, UserChannel>>;
 
 #[derive(Clone)]
 pub struct AppSate(Arc<AppStateInner>);
@@ -31,21 +50,51 @@ pub struct AppSate(Arc<AppStateInner>);
 pub struct AppStateInner {
     pub config: AppConfig,
     users: UserMap,
-    dk: DecodingKey,
+    // seeded with the start time so ids keep increasing across restarts
+    next_event_id: AtomicU64,
+    dk: RwLock<DecodingKey>,
+    jwks_fetched_at: Mutex<Option<Instant>>,
+    listener: RwLock<ListenerHealth>,
+    pool: PgPool,
 }
 
 pub async fn get_router(config: AppConfig) -> anyhow::Result<Router> {
     let state = AppSate::new(config);
+    if let Err(e) = state.refresh_keys().await {
+        warn!("fetch jwks failed: {}", e);
+    }
 
     setup_pg_listener(state.clone()).await?;
+    spawn_channel_sweeper(state.clone());
     let app = Router::new()
         .route("/events", get(sse_handler))
+        .route("/ws", get(ws_handler))
         .layer(from_fn_with_state(state.clone(), verify_token::<AppSate>))
         .route("/", get(index_handler))
+        .route("/metrics", get(metrics_handler))
+        .route("/health", get(health_handler))
         .with_state(state.clone());
     Ok(app)
 }
 
+/// drop the channels of users who have been disconnected for longer than `channel_idle_ttl`
+fn spawn_channel_sweeper(state: AppSate) {
+    let ttl = Duration::from_secs(state.config.server.channel_idle_ttl);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            state.users.retain(|user_id, channel| {
+                let idle = channel.is_idle(ttl);
+                if idle {
+                    info!("user `{}` channel removed after being idle", user_id);
+                }
+                !idle
+            });
+        }
+    });
+}
+
 async fn index_handler() -> impl IntoResponse {
     Html(INDEX_HTML)
 }
@@ -53,8 +102,25 @@ async fn index_handler() -> impl IntoResponse {
 impl TokenVerify for AppSate {
     type Error = AppError;
 
-    fn verify(&self, token: &str) -> Result<User, Self::Error> {
-        Ok(self.dk.verify(token)?)
+    async fn verify(&self, token: &str) -> Result<User, Self::Error> {
+        if let Some(kid) = DecodingKey::token_kid(token) {
+            let known = self.dk.read().expect("dk lock poisoned").contains(&kid);
+            if !known {
+                if let Err(e) = self.refresh_keys().await {
+                    warn!("fetch jwks failed: {}", e);
+                }
+            }
+        }
+        let claims = self.dk.read().expect("dk lock poisoned").decode(token)?;
+        // revoked, the member was deactivated or their role changed
+        let user = claims.custom;
+        let revoked = user
+            .is_token_revoked(&self.pool, claims.jwt_id.as_deref())
+            .await?;
+        if revoked {
+            return Err(AppError::TokenRevoked);
+        }
+        Ok(user)
     }
 }
 
@@ -67,8 +133,87 @@ impl Deref for AppSate {
 }
 impl AppSate {
     pub fn new(config: AppConfig) -> Self {
-        let dk = DecodingKey::load(&config.auth.pk).expect("Failed to load public key");
+        let dk = load_static_keys(&config.auth).expect("Failed to load public key");
         let users = Arc::new(DashMap::new());
-        Self(Arc::new(AppStateInner { config, users, dk }))
+        let pool = PgPool::connect_lazy(&config.server.db_url).expect("Failed to parse db_url");
+        Self(Arc::new(AppStateInner {
+            config,
+            users,
+            next_event_id: AtomicU64::new(Utc::now().timestamp_micros() as _),
+            dk: RwLock::new(dk),
+            jwks_fetched_at: Mutex::new(None),
+            listener: RwLock::new(ListenerHealth::default()),
+            pool,
+        }))
+    }
+
+    pub(crate) fn listener_health(&self) -> ListenerHealth {
+        self.listener
+            .read()
+            .expect("listener lock poisoned")
+            .clone()
+    }
+
+    pub(crate) fn set_listener_health(&self, connected: bool, error: Option<String>) {
+        let mut health = self.listener.write().expect("listener lock poisoned");
+        if health.connected != connected {
+            health.since = Utc::now();
+            // only an outage has an error, the first connect doesn't count
+            if connected && health.last_error.is_some() {
+                health.reconnects += 1;
+            }
+        }
+        health.connected = connected;
+        if error.is_some() {
+            health.last_error = error;
+        }
+    }
+
+    /// subscribe to the events of a user after `last_event_id`, all transports share the same channel
+    pub(crate) fn subscribe(&self, user_id: u64, last_event_id: Option<u64>) -> impl Stream<Item = Arc<IdEvent>> {
+        let stream = self
+            .users
+            .entry(user_id)
+            .or_insert_with(|| UserChannel::new(&self.next_event_id))
+            .subscribe(last_event_id);
+        info!("user `{}` subscribed to broadcast channel", user_id);
+        stream
+    }
+
+    /// send the event to those of the users who are connected
+    pub(crate) fn send_event(&self, user_ids: impl IntoIterator<Item = u64>, event: Arc<AppEvent>) {
+        for user_id in user_ids {
+            if let Some(channel) = self.users.get(&user_id) {
+                info!("Sending notification to user: {}", user_id);
+                channel.send(&self.next_event_id, event.clone());
+            }
+        }
+    }
+
+    /// replace the keyring with the static keys plus the keys currently published by chat_server
+    async fn refresh_keys(&self) -> Result<(), AppError> {
+        let Some(url) = &self.config.auth.jwks_url else {
+            return Ok(());
+        };
+        {
+            let mut fetched_at = self.jwks_fetched_at.lock().expect("jwks lock poisoned");
+            if fetched_at.is_some_and(|t| t.elapsed() < JWKS_REFETCH_INTERVAL) {
+                return Ok(());
+            }
+            *fetched_at = Some(Instant::now());
+        }
+        let jwks: Jwks = reqwest::get(url).await?.error_for_status()?.json().await?;
+        let mut dk = load_static_keys(&self.config.auth)?;
+        dk.extend(DecodingKey::from_jwks(&jwks)?);
+        *self.dk.write().expect("dk lock poisoned") = dk;
+        Ok(())
+    }
+}
+
+fn load_static_keys(config: &AuthConfig) -> Result<DecodingKey, jwt_simple::Error> {
+    let mut dk = DecodingKey::default();
+    for key in &config.keys {
+        dk.add(&key.kid, &key.pk)?;
     }
+    Ok(dk)
 }
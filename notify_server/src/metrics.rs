@@ -0,0 +1,39 @@
+use std::fmt::Write;
+
+use axum::{extract::State, http::header, response::IntoResponse};
+
+use crate::AppSate;
+
+/// connection metrics in the prometheus text format
+pub(crate) async fn metrics_handler(State(state): State<AppSate>) -> impl IntoResponse {
+    let mut users = 0;
+    let mut connections = 0;
+    for channel in state.users.iter() {
+        let subscribers = channel.subscribers();
+        if subscribers > 0 {
+            users += 1;
+        }
+        connections += subscribers;
+    }
+    let gauges = [
+        (
+            "notify_user_channels",
+            "Users with a channel, connected or within the idle ttl.",
+            state.users.len(),
+        ),
+        ("notify_connected_users", "Users with at least one connection.", users),
+        ("notify_connections", "Live SSE and websocket connections.", connections),
+        (
+            "notify_listener_up",
+            "Whether the postgres listener is connected.",
+            state.listener_health().connected as usize,
+        ),
+    ];
+    let mut body = String::new();
+    for (name, help, value) in gauges {
+        let _ = writeln!(body, "# HELP {name} {help}");
+        let _ = writeln!(body, "# TYPE {name} gauge");
+        let _ = writeln!(body, "{name} {value}");
+    }
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
@@ -1,20 +1,70 @@
-use std::{collections::HashSet, sync::Arc};
+use std::{collections::HashSet, sync::Arc, time::Duration};
 
-use chat_core::{Chat, Message};
-use futures::StreamExt;
+use chat_core::{Chat, ChatRead, Message};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgListener;
+use tokio::time::sleep;
 use tracing::{info, warn};
 
 use crate::AppSate;
 
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(tag = "event")]
 pub enum AppEvent {
     NewChat(Chat),
     AddToChat(Chat),
     RemoveFromChat(Chat),
+    // name, type or members of a chat the user stays in changed
+    ChatUpdated(Chat),
     NewMessage(Message),
+    NewReply(Message),
+    MessageUpdated(Message),
+    MessageDeleted(Message),
+    ReactionChanged(ReactionChanged),
+    ReadUpdated(ChatRead),
+    Typing(Typing),
+    // events were missed, the client should refetch its state
+    Resync,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Typing {
+    pub chat_id: i64,
+    pub user_id: i64,
+}
+
+/// the new count of `emoji` on the message after `user_id` added or removed theirs
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReactionChanged {
+    pub chat_id: i64,
+    pub message_id: i64,
+    pub user_id: i64,
+    pub emoji: String,
+    pub count: i64,
+    pub added: bool,
+}
+
+impl AppEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AppEvent::NewChat(_) => "NewChat",
+            AppEvent::AddToChat(_) => "AddToChat",
+            AppEvent::RemoveFromChat(_) => "RemoveFromChat",
+            AppEvent::ChatUpdated(_) => "ChatUpdated",
+            AppEvent::NewMessage(_) => "NewMessage",
+            AppEvent::NewReply(_) => "NewReply",
+            AppEvent::MessageUpdated(_) => "MessageUpdated",
+            AppEvent::MessageDeleted(_) => "MessageDeleted",
+            AppEvent::ReactionChanged(_) => "ReactionChanged",
+            AppEvent::ReadUpdated(_) => "ReadUpdated",
+            AppEvent::Typing(_) => "Typing",
+            AppEvent::Resync => "Resync",
+        }
+    }
 }
 #[derive(Debug)]
 struct Notification {
@@ -31,92 +81,275 @@ struct ChatUpdated {
     new: Option<Chat>,
 }
 
-// pg_notify('chat_message_created', row_to_json(NEW)::text);
+// pg_notify('chat_message_created' | 'chat_message_replied' | 'chat_message_updated' | 'chat_message_deleted',
+//   json_build_object('message', NEW, 'members', USERS)::text);
+// for replies `members` are the chat members who took part in the thread
 #[derive(Debug, Serialize, Deserialize)]
-struct ChatMessageCreated {
+struct ChatMessageChanged {
     message: Message,
     members: Vec<i64>,
 }
 
+// pg_notify('message_reaction_changed', json_build_object('reaction', json_build_object(...), 'members', USERS)::text);
+#[derive(Debug, Serialize, Deserialize)]
+struct MessageReactionChanged {
+    reaction: ReactionChanged,
+    members: Vec<i64>,
+}
+
+// pg_notify('chat_read_updated', json_build_object('read', NEW, 'members', USERS)::text);
+// `members` is the reader, plus the peer in single chats
+#[derive(Debug, Serialize, Deserialize)]
+struct ChatReadUpdated {
+    read: ChatRead,
+    members: Vec<i64>,
+}
+
+/// connection state of the postgres listener, served on `/health`
+#[derive(Debug, Clone, Serialize)]
+pub struct ListenerHealth {
+    pub connected: bool,
+    pub since: DateTime<Utc>,
+    pub reconnects: u64,
+    pub last_error: Option<String>,
+}
+
+impl Default for ListenerHealth {
+    fn default() -> Self {
+        Self {
+            connected: false,
+            since: Utc::now(),
+            reconnects: 0,
+            last_error: None,
+        }
+    }
+}
+
+/// connects and listens before returning so a bad db_url fails startup, afterwards the
+/// listener is supervised: lost connections are re-established with backoff and
+/// clients are told to resync since notifications may have been missed meanwhile.
 pub async fn setup_pg_listener(state: AppSate) -> anyhow::Result<()> {
-    let mut listener = PgListener::connect(&state.config.server.db_url).await?;
-    listener.listen("chat_updated").await?;
-    listener.listen("chat_message_created").await?;
-
-    let mut stream = listener.into_stream();
-
-    tokio::spawn(async move {
-        while let Some(Ok(notif)) = stream.next().await {
-            info!("Received notification: {:?}", notif);
-            let notification = Notification::load(notif.channel(), notif.payload())?;
-            let users = &state.users;
-            for user_id in notification.user_ids {
-                if let Some(tx) = users.get(&user_id) {
-                    info!("Sending notification to user: {}", user_id);
-                    if let Err(e) = tx.send(notification.event.clone()) {
-                        warn!("Failed to send notification to user: {}:{}", user_id, e);
+    let listener = connect_listener(&state.config.server.db_url).await?;
+    state.set_listener_health(true, None);
+    tokio::spawn(supervise_listener(state, listener));
+    Ok(())
+}
+
+async fn connect_listener(db_url: &str) -> Result<PgListener, sqlx::Error> {
+    let mut listener = PgListener::connect(db_url).await?;
+    listener
+        .listen_all([
+            "chat_updated",
+            "chat_message_created",
+            "chat_message_replied",
+            "chat_message_updated",
+            "chat_message_deleted",
+            "message_reaction_changed",
+            "chat_read_updated",
+        ])
+        .await?;
+    Ok(listener)
+}
+
+async fn supervise_listener(state: AppSate, mut listener: PgListener) {
+    loop {
+        let err = match listener.try_recv().await {
+            Ok(Some(notif)) => {
+                info!("Received notification: {:?}", notif);
+                match Notification::load(notif.channel(), notif.payload()) {
+                    Ok(notifications) => {
+                        for notification in notifications {
+                            state.send_event(notification.user_ids, notification.event);
+                        }
                     }
+                    Err(e) => warn!("Skipping bad notification on {}: {}", notif.channel(), e),
                 }
+                continue;
             }
-        }
-        Ok::<_, anyhow::Error>(())
-    });
+            Ok(None) => "connection lost".to_string(),
+            Err(e) => e.to_string(),
+        };
 
-    Ok(())
+        warn!("pg listener disconnected: {}", err);
+        state.set_listener_health(false, Some(err));
+        let mut backoff = MIN_BACKOFF;
+        listener = loop {
+            sleep(backoff).await;
+            match connect_listener(&state.config.server.db_url).await {
+                Ok(listener) => break listener,
+                Err(e) => {
+                    warn!("pg listener reconnect failed, retrying in {:?}: {}", backoff, e);
+                    state.set_listener_health(false, Some(e.to_string()));
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        };
+        info!("pg listener reconnected");
+        state.set_listener_health(true, None);
+        let user_ids: Vec<_> = state.users.iter().map(|v| *v.key()).collect();
+        state.send_event(user_ids, Arc::new(AppEvent::Resync));
+    }
 }
 
 impl Notification {
-    fn load(r#type: &str, payload: &str) -> anyhow::Result<Self> {
+    fn new(user_ids: HashSet<u64>, event: AppEvent) -> Self {
+        Self {
+            user_ids,
+            event: Arc::new(event),
+        }
+    }
+
+    fn load(r#type: &str, payload: &str) -> anyhow::Result<Vec<Self>> {
         match r#type {
             "chat_updated" => {
                 let payload: ChatUpdated = serde_json::from_str(payload)?;
-                let user_ids = get_affected_chat_user_ids(payload.old.as_ref(), payload.new.as_ref());
-                let event = match payload.op.as_str() {
-                    "INSERT" => AppEvent::NewChat(payload.new.expect("new should exist")),
-                    "UPDATE" => AppEvent::AddToChat(payload.new.expect("new should exist")),
-                    "DELETE" => AppEvent::RemoveFromChat(payload.old.expect("old should exist")),
+                let notifications = match (payload.op.as_str(), payload.old, payload.new) {
+                    ("INSERT", _, Some(new)) => vec![Self::new(chat_user_ids(&new), AppEvent::NewChat(new))],
+                    ("UPDATE", Some(old), Some(new)) => {
+                        let (added, removed, kept) = get_affected_chat_user_ids(&old, &new);
+                        let mut notifications = vec![];
+                        if !removed.is_empty() {
+                            notifications.push(Self::new(removed, AppEvent::RemoveFromChat(new.clone())));
+                        }
+                        if !added.is_empty() {
+                            notifications.push(Self::new(added, AppEvent::AddToChat(new.clone())));
+                        }
+                        if !kept.is_empty() {
+                            notifications.push(Self::new(kept, AppEvent::ChatUpdated(new)));
+                        }
+                        notifications
+                    }
+                    ("DELETE", Some(old), _) => vec![Self::new(chat_user_ids(&old), AppEvent::RemoveFromChat(old))],
                     _ => return Err(anyhow::anyhow!("Invalid operation")),
                 };
-                Ok(Self {
-                    user_ids,
-                    event: Arc::new(event),
-                })
+                Ok(notifications)
             }
-            "chat_message_created" => {
-                let payload: ChatMessageCreated = serde_json::from_str(payload)?;
+            "chat_message_created" | "chat_message_replied" | "chat_message_updated" | "chat_message_deleted" => {
+                let payload: ChatMessageChanged = serde_json::from_str(payload)?;
                 let user_ids = payload.members.iter().map(|v| *v as u64).collect();
-                Ok(Self {
-                    user_ids,
-                    event: Arc::new(AppEvent::NewMessage(payload.message)),
-                })
+                let event = match r#type {
+                    "chat_message_created" => AppEvent::NewMessage(payload.message),
+                    "chat_message_replied" => AppEvent::NewReply(payload.message),
+                    "chat_message_updated" => AppEvent::MessageUpdated(payload.message),
+                    _ => AppEvent::MessageDeleted(payload.message),
+                };
+                Ok(vec![Self::new(user_ids, event)])
+            }
+            "message_reaction_changed" => {
+                let payload: MessageReactionChanged = serde_json::from_str(payload)?;
+                let user_ids = payload.members.iter().map(|v| *v as u64).collect();
+                Ok(vec![Self::new(user_ids, AppEvent::ReactionChanged(payload.reaction))])
+            }
+            "chat_read_updated" => {
+                let payload: ChatReadUpdated = serde_json::from_str(payload)?;
+                let user_ids = payload.members.iter().map(|v| *v as u64).collect();
+                Ok(vec![Self::new(user_ids, AppEvent::ReadUpdated(payload.read))])
             }
             _ => Err(anyhow::anyhow!("Invalid event type")),
         }
     }
 }
 
-fn get_affected_chat_user_ids(old: Option<&Chat>, new: Option<&Chat>) -> HashSet<u64> {
-    match (old, new) {
-        (Some(old), Some(new)) => {
-            // diff old/new members, if identical, no need to notify, otherwise notify the union of both
-            let old_user_ids = old
-                .members
-                .iter()
-                .map(|v| *v as u64)
-                .collect::<HashSet<u64>>();
-            let new_user_ids = new
-                .members
-                .iter()
-                .map(|v| *v as u64)
-                .collect::<HashSet<u64>>();
-            if old_user_ids == new_user_ids {
-                HashSet::new()
-            } else {
-                old_user_ids.union(&new_user_ids).cloned().collect()
-            }
+fn chat_user_ids(chat: &Chat) -> HashSet<u64> {
+    chat.members.iter().map(|v| *v as u64).collect()
+}
+
+/// returns (users to receive `AddToChat`, users to receive `RemoveFromChat`, users to receive
+/// `ChatUpdated`): the ones added, the ones removed and the ones who were in it already.
+fn get_affected_chat_user_ids(old: &Chat, new: &Chat) -> (HashSet<u64>, HashSet<u64>, HashSet<u64>) {
+    let old_user_ids = chat_user_ids(old);
+    let new_user_ids = chat_user_ids(new);
+    let added = new_user_ids.difference(&old_user_ids).cloned().collect();
+    let removed = old_user_ids.difference(&new_user_ids).cloned().collect();
+    let kept = new_user_ids.intersection(&old_user_ids).cloned().collect();
+    (added, removed, kept)
+}
+
+#[cfg(test)]
+mod tests {
+    use chat_core::ChatType;
+
+    use super::*;
+
+    fn chat(members: &[i64]) -> Chat {
+        Chat {
+            id: 1,
+            ws_id: 1,
+            name: None,
+            topic: None,
+            r#type: ChatType::Group,
+            members: members.to_vec(),
+            created_at: chrono::Utc::now(),
+            unread: 0,
+            last_message: None,
         }
-        (None, Some(new)) => new.members.iter().map(|v| *v as u64).collect(),
-        (Some(old), None) => old.members.iter().map(|v| *v as u64).collect(),
-        (None, None) => HashSet::new(),
+    }
+
+    #[test]
+    fn bad_notification_should_error() {
+        assert!(Notification::load("chat_updated", "not json").is_err());
+        assert!(Notification::load("chat_updated", r#"{"op": "TRUNCATE"}"#).is_err());
+        assert!(Notification::load("unknown", "{}").is_err());
+    }
+
+    #[test]
+    fn reaction_notification_should_load() -> anyhow::Result<()> {
+        let payload = r#"{"reaction": {"chat_id": 1, "message_id": 2, "user_id": 3, "emoji": "👍", "count": 2, "added": true}, "members": [1, 3]}"#;
+        let notifications = Notification::load("message_reaction_changed", payload)?;
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].user_ids, HashSet::from([1, 3]));
+        let AppEvent::ReactionChanged(reaction) = notifications[0].event.as_ref() else {
+            panic!("expected ReactionChanged");
+        };
+        assert_eq!((reaction.message_id, reaction.count), (2, 2));
+        Ok(())
+    }
+
+    #[test]
+    fn message_notification_should_not_make_up_file_metadata() -> anyhow::Result<()> {
+        let payload = r#"{"message": {"id": 1, "chat_id": 1, "sender_id": 1, "content": "hi", "files": ["/files/1/abc/def/0123.txt"], "created_at": "2024-08-01T00:00:00Z"}, "members": [1, 2]}"#;
+        let notifications = Notification::load("chat_message_created", payload)?;
+        let AppEvent::NewMessage(message) = notifications[0].event.as_ref() else {
+            panic!("expected NewMessage");
+        };
+        assert_eq!(message.files[0].name, "0123.txt");
+        assert_eq!((message.files[0].size, message.files[0].mime.as_deref()), (None, None));
+        let event = serde_json::to_value(&message.files[0])?;
+        assert!(event.get("size").is_none() && event.get("mime").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn affected_chat_user_ids_should_work() {
+        let (added, removed, kept) = get_affected_chat_user_ids(&chat(&[1, 2, 3]), &chat(&[1, 2, 3]));
+        assert!(added.is_empty());
+        assert!(removed.is_empty());
+        assert_eq!(kept, HashSet::from([1, 2, 3]));
+
+        let (added, removed, kept) = get_affected_chat_user_ids(&chat(&[1, 2, 3]), &chat(&[1, 2, 3, 4]));
+        assert_eq!(added, HashSet::from([4]));
+        assert!(removed.is_empty());
+        assert_eq!(kept, HashSet::from([1, 2, 3]));
+
+        let (added, removed, kept) = get_affected_chat_user_ids(&chat(&[1, 2, 3]), &chat(&[1, 3, 5]));
+        assert_eq!(added, HashSet::from([5]));
+        assert_eq!(removed, HashSet::from([2]));
+        assert_eq!(kept, HashSet::from([1, 3]));
+    }
+
+    #[test]
+    fn renamed_chat_should_notify_its_members() -> anyhow::Result<()> {
+        let mut new = chat(&[1, 2]);
+        new.name = Some("renamed".to_string());
+        let payload = serde_json::to_string(&ChatUpdated {
+            op: "UPDATE".to_string(),
+            old: Some(chat(&[1, 2])),
+            new: Some(new),
+        })?;
+        let notifications = Notification::load("chat_updated", &payload)?;
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].user_ids, HashSet::from([1, 2]));
+        assert_eq!(notifications[0].event.name(), "ChatUpdated");
+        Ok(())
     }
 }
@@ -2,44 +2,34 @@ use std::{convert::Infallible, time::Duration};
 
 use axum::{
     extract::State,
+    http::HeaderMap,
     response::{sse::Event, Sse},
     Extension,
 };
 use chat_core::User;
-use futures::Stream;
-use tokio::sync::broadcast;
-use tokio_stream::{wrappers::BroadcastStream, StreamExt};
-use tracing::info;
+use futures::{Stream, StreamExt};
 
 use crate::AppSate;
 
-const CHANNEL_CAPACITY: usize = 256;
 pub(crate) async fn sse_handler(
     Extension(user): Extension<User>,
     State(state): State<AppSate>,
+    headers: HeaderMap,
     // TypedHeader(user_agent): TypedHeader<headers::UserAgent>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
     // info!("`{}` connected to server", user_agent.as_str());
-    let user_id = user.id as u64;
-    let users = &state.users;
-    let rx = if let Some(tx) = users.get(&user_id) {
-        tx.subscribe()
-    } else {
-        let (tx, rx) = broadcast::channel(CHANNEL_CAPACITY);
-        state.users.insert(user_id, tx);
-        rx
-    };
-    info!("user `{}` subscribed to broadcast channel", user_id);
-
-    let stream = BroadcastStream::new(rx).filter_map(|v| v.ok()).map(|v| {
-        let name = match v.as_ref() {
-            crate::notify::AppEvent::NewChat(_) => "NewChat",
-            crate::notify::AppEvent::AddToChat(_) => "AddToChat",
-            crate::notify::AppEvent::RemoveFromChat(_) => "RemoveFromChat",
-            crate::notify::AppEvent::NewMessage(_) => "NewMessage",
-        };
-        let v = serde_json::to_string(&v).expect("Failed to serialize event");
-        Ok(Event::default().data(v).event(name))
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok());
+    let stream = state.subscribe(user.id as u64, last_event_id).map(|v| {
+        // the id goes into the SSE `id` field, data is the bare event
+        let data = serde_json::to_string(&v.event).expect("Failed to serialize event");
+        let event = Event::default().data(data).event(v.event.name());
+        Ok(match v.id {
+            Some(id) => event.id(id.to_string()),
+            None => event,
+        })
     });
 
     Sse::new(stream).keep_alive(
@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{
+        ws::{Message as WsMessage, WebSocket},
+        Query, State, WebSocketUpgrade,
+    },
+    response::IntoResponse,
+    Extension,
+};
+use chat_core::{ChatRead, User};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    error::AppError,
+    notify::{AppEvent, Typing},
+    AppSate,
+};
+
+/// frames sent by the client, e.g. `{"action": "Typing", "chat_id": 1}`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action")]
+enum ClientFrame {
+    Ping,
+    Typing { chat_id: i64 },
+    MarkRead { chat_id: i64, message_id: i64 },
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct WsParams {
+    // browsers can't set headers on websockets, this replaces `Last-Event-ID`
+    last_event_id: Option<u64>,
+}
+
+/// direct replies to client frames, events are sent as they are serialized for SSE
+#[derive(Debug, Serialize)]
+#[serde(tag = "event")]
+enum Reply {
+    Pong,
+    Error { message: String },
+}
+
+pub(crate) async fn ws_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppSate>,
+    Query(params): Query<WsParams>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| handle_socket(socket, state, user, params.last_event_id))
+}
+
+async fn handle_socket(socket: WebSocket, state: AppSate, user: User, last_event_id: Option<u64>) {
+    let (mut sender, mut receiver) = socket.split();
+    let events = state.subscribe(user.id as u64, last_event_id);
+    tokio::pin!(events);
+
+    loop {
+        let frame = tokio::select! {
+            event = events.next() => match event {
+                Some(event) => serde_json::to_string(&event).expect("Failed to serialize event"),
+                None => break,
+            },
+            msg = receiver.next() => match msg {
+                Some(Ok(WsMessage::Text(text))) => match handle_frame(&state, &user, &text).await {
+                    Some(reply) => serde_json::to_string(&reply).expect("Failed to serialize reply"),
+                    None => continue,
+                },
+                // ping/pong frames are answered by axum
+                Some(Ok(WsMessage::Binary(_) | WsMessage::Ping(_) | WsMessage::Pong(_))) => continue,
+                Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+            },
+        };
+        if sender.send(WsMessage::Text(frame)).await.is_err() {
+            break;
+        }
+    }
+    info!("user `{}` websocket closed", user.id);
+}
+
+async fn handle_frame(state: &AppSate, user: &User, text: &str) -> Option<Reply> {
+    let frame = match serde_json::from_str::<ClientFrame>(text) {
+        Ok(frame) => frame,
+        Err(e) => {
+            return Some(Reply::Error {
+                message: format!("invalid frame: {}", e),
+            })
+        }
+    };
+    let ret = match frame {
+        ClientFrame::Ping => Ok(Some(Reply::Pong)),
+        ClientFrame::Typing { chat_id } => typing(state, user, chat_id).await.map(|_| None),
+        ClientFrame::MarkRead { chat_id, message_id } => mark_read(state, user, chat_id, message_id)
+            .await
+            .map(|_| None),
+    };
+    ret.unwrap_or_else(|e| Some(Reply::Error { message: e.to_string() }))
+}
+
+/// tell the other members of the chat that the user is typing
+async fn typing(state: &AppSate, user: &User, chat_id: i64) -> Result<(), AppError> {
+    let members: Option<Vec<i64>> =
+        sqlx::query_scalar("SELECT members FROM chats WHERE id = $1 AND ws_id = $2 AND $3 = ANY(members)")
+            .bind(chat_id)
+            .bind(user.ws_id)
+            .bind(user.id)
+            .fetch_optional(&state.pool)
+            .await?;
+    let Some(members) = members else {
+        return Err(AppError::NotFound(format!("chat {}", chat_id)));
+    };
+    let event = Arc::new(AppEvent::Typing(Typing {
+        chat_id,
+        user_id: user.id,
+    }));
+    let user_ids = members
+        .into_iter()
+        .filter(|id| *id != user.id)
+        .map(|id| id as u64);
+    state.send_event(user_ids, event);
+    Ok(())
+}
+
+/// same as `POST /api/chats/:id/read`, the `ReadUpdated` event comes back through the listener
+async fn mark_read(state: &AppSate, user: &User, chat_id: i64, message_id: i64) -> Result<(), AppError> {
+    match ChatRead::mark(&state.pool, chat_id, user.id, user.ws_id, message_id).await? {
+        Some(_) => Ok(()),
+        None => Err(AppError::NotFound(format!("message {}", message_id))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_frame_should_parse() {
+        let frame: ClientFrame = serde_json::from_str(r#"{"action": "Typing", "chat_id": 1}"#).unwrap();
+        assert!(matches!(frame, ClientFrame::Typing { chat_id: 1 }));
+        let frame: ClientFrame = serde_json::from_str(r#"{"action": "Ping"}"#).unwrap();
+        assert!(matches!(frame, ClientFrame::Ping));
+        let frame: ClientFrame =
+            serde_json::from_str(r#"{"action": "MarkRead", "chat_id": 1, "message_id": 2}"#).unwrap();
+        assert!(matches!(
+            frame,
+            ClientFrame::MarkRead {
+                chat_id: 1,
+                message_id: 2
+            }
+        ));
+        assert!(serde_json::from_str::<ClientFrame>(r#"{"action": "Shout"}"#).is_err());
+
+        let reply = serde_json::to_string(&Reply::Pong).unwrap();
+        assert_eq!(reply, r#"{"event":"Pong"}"#);
+    }
+}